@@ -0,0 +1,279 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::types::kline::Kline;
+use crate::types::order::OrderSide;
+
+pub const FUNDING_INTERVAL_MS: i64 = 8 * 60 * 60 * 1000; // 00:00, 08:00 and 16:00 UTC
+pub const BNB_FEE_DISCOUNT: f64 = 0.1;
+
+// USDⓈ-M futures (maker, taker) rates of VIP 0 to 9
+const VIP_FEE_RATES: [(f64, f64); 10] = [
+    (0.0002, 0.0005),
+    (0.00016, 0.0004),
+    (0.00014, 0.00035),
+    (0.00012, 0.00032),
+    (0.0001, 0.0003),
+    (0.00008, 0.00027),
+    (0.00006, 0.00025),
+    (0.00004, 0.00022),
+    (0.00002, 0.0002),
+    (0., 0.00017),
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Liquidity {
+    Maker, // resting limit orders, take profits
+    Taker, // market orders, stop losses
+}
+
+/// Binance fee schedule of a VIP tier, `maker_rate` and `taker_rate`
+/// override the tier's rates. Paying fees in BNB takes 10% off.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeModel {
+    #[serde(default)]
+    pub vip_tier: usize,
+    #[serde(default)]
+    pub bnb_discount: bool,
+    pub maker_rate: Option<f64>,
+    pub taker_rate: Option<f64>,
+}
+
+impl FeeModel {
+    pub fn rate(&self, liquidity: Liquidity) -> f64 {
+        let tier = self.vip_tier.min(VIP_FEE_RATES.len() - 1);
+        let (maker_rate, taker_rate) = VIP_FEE_RATES[tier];
+        let rate = match liquidity {
+            Liquidity::Maker => self.maker_rate.unwrap_or(maker_rate),
+            Liquidity::Taker => self.taker_rate.unwrap_or(taker_rate),
+        };
+        if self.bnb_discount {
+            rate * (1. - BNB_FEE_DISCOUNT)
+        } else {
+            rate
+        }
+    }
+}
+
+/// Price levels of one side of an order book, best first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrderBookSnapshot {
+    pub bids: Vec<(f64, f64)>, // (price, quantity)
+    pub asks: Vec<(f64, f64)>,
+}
+
+impl OrderBookSnapshot {
+    pub fn mid_price(&self) -> Option<f64> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some((bid, _)), Some((ask, _))) => Some((bid + ask) / 2.),
+            _ => None,
+        }
+    }
+
+    /// Average price of a market order walking the book, none if the book
+    /// side is empty. What the book can't fill trades at its last level.
+    pub fn average_fill_price(&self, side: &OrderSide, size: f64) -> Option<f64> {
+        let levels = match side {
+            OrderSide::Buy => &self.asks,
+            OrderSide::Sell => &self.bids,
+        };
+        let (last_price, _) = levels.last()?;
+        let mut remaining = size;
+        let mut cost = 0.;
+        for (price, quantity) in levels.iter() {
+            let filled = remaining.min(*quantity);
+            cost += filled * price;
+            remaining -= filled;
+            if remaining <= 0. {
+                break;
+            }
+        }
+        if remaining > 0. {
+            warn!(
+                "Order of {} exceeds the order book depth, the rest fills at {}",
+                size, last_price
+            );
+            cost += remaining * last_price;
+        }
+        Some(cost / size)
+    }
+}
+
+/// How far market fills trade from the reference price, always against the
+/// order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlippageModel {
+    #[default]
+    None,
+    FixedBps {
+        bps: f64,
+    },
+    // Fraction of the kline's high - low range
+    BarRange {
+        fraction: f64,
+    },
+    // Impact of walking the snapshot, relative to its mid price
+    OrderBook(OrderBookSnapshot),
+}
+
+impl SlippageModel {
+    pub fn fill_price(&self, side: &OrderSide, price: f64, size: f64, kline: &Kline) -> f64 {
+        let slippage = match self {
+            SlippageModel::None => 0.,
+            SlippageModel::FixedBps { bps } => price * bps / 10000.,
+            SlippageModel::BarRange { fraction } => (kline.high - kline.low) * fraction,
+            SlippageModel::OrderBook(book) => {
+                match (book.mid_price(), book.average_fill_price(side, size)) {
+                    (Some(mid_price), Some(fill_price)) if size > 0. => {
+                        price * (fill_price - mid_price).abs() / mid_price
+                    }
+                    _ => 0.,
+                }
+            }
+        };
+        match side {
+            OrderSide::Buy => price + slippage,
+            OrderSide::Sell => price - slippage,
+        }
+    }
+}
+
+/// Funding paid or received on open positions. `History` uses the rates
+/// given to `Backtester::with_funding_rates`, Binance settles them at the
+/// 8h marks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FundingModel {
+    #[default]
+    None,
+    Fixed {
+        rate: f64, // every 8h mark
+    },
+    History,
+}
+
+/// The 8h funding marks within `from_ts..=to_ts`.
+pub fn funding_marks(from_ts: i64, to_ts: i64) -> impl Iterator<Item = i64> {
+    let first = (from_ts + FUNDING_INTERVAL_MS - 1).div_euclid(FUNDING_INTERVAL_MS);
+    let last = to_ts.div_euclid(FUNDING_INTERVAL_MS);
+    (first..=last).map(|mark| mark * FUNDING_INTERVAL_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: i64 = 60 * 60 * 1000;
+    const DAY_START: i64 = 1_672_531_200_000; // 2023-01-01 00:00 UTC
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn fee_model_picks_the_rate_of_the_liquidity() {
+        let vip0 = FeeModel::default();
+        assert_eq!(vip0.rate(Liquidity::Maker), 0.0002);
+        assert_eq!(vip0.rate(Liquidity::Taker), 0.0005);
+        let vip3 = FeeModel {
+            vip_tier: 3,
+            ..Default::default()
+        };
+        assert_eq!(vip3.rate(Liquidity::Maker), 0.00012);
+        assert_eq!(vip3.rate(Liquidity::Taker), 0.00032);
+        // Tiers past the last one get its rates
+        let vip20 = FeeModel {
+            vip_tier: 20,
+            ..Default::default()
+        };
+        assert_eq!(vip20.rate(Liquidity::Taker), 0.00017);
+    }
+
+    #[test]
+    fn fee_model_overrides_and_bnb_discount() {
+        let custom = FeeModel {
+            maker_rate: Some(-0.0001),
+            ..Default::default()
+        };
+        assert_eq!(custom.rate(Liquidity::Maker), -0.0001);
+        assert_eq!(custom.rate(Liquidity::Taker), 0.0005);
+        let bnb = FeeModel {
+            bnb_discount: true,
+            ..Default::default()
+        };
+        assert_close(bnb.rate(Liquidity::Maker), 0.00018);
+        assert_close(bnb.rate(Liquidity::Taker), 0.00045);
+    }
+
+    #[test]
+    fn slippage_trades_against_the_order() {
+        let kline = Kline {
+            high: 20100.,
+            low: 19900.,
+            ..Default::default()
+        };
+        let fill =
+            |model: &SlippageModel, side: OrderSide| model.fill_price(&side, 20000., 1., &kline);
+        let bps = SlippageModel::FixedBps { bps: 10. };
+        assert_close(fill(&bps, OrderSide::Buy), 20020.);
+        assert_close(fill(&bps, OrderSide::Sell), 19980.);
+        let range = SlippageModel::BarRange { fraction: 0.1 };
+        assert_close(fill(&range, OrderSide::Buy), 20020.);
+        assert_close(fill(&range, OrderSide::Sell), 19980.);
+        assert_eq!(fill(&SlippageModel::None, OrderSide::Buy), 20000.);
+        assert_eq!(fill(&SlippageModel::None, OrderSide::Sell), 20000.);
+    }
+
+    #[test]
+    fn order_book_slippage_walks_the_levels() {
+        let book = OrderBookSnapshot {
+            bids: vec![(99., 1.), (98., 2.)],
+            asks: vec![(101., 1.), (102., 2.)],
+        };
+        assert_eq!(book.mid_price(), Some(100.));
+        assert_eq!(book.average_fill_price(&OrderSide::Buy, 2.), Some(101.5));
+        assert_eq!(book.average_fill_price(&OrderSide::Sell, 2.), Some(98.5));
+        // Beyond the depth the rest fills at the last level
+        assert_close(
+            book.average_fill_price(&OrderSide::Buy, 5.).unwrap(),
+            (101. + 4. * 102.) / 5.,
+        );
+
+        let model = SlippageModel::OrderBook(book);
+        let kline = Kline::default();
+        // The impact relative to the mid price applies to the fill price
+        assert_close(model.fill_price(&OrderSide::Buy, 200., 2., &kline), 203.);
+        assert_close(model.fill_price(&OrderSide::Sell, 200., 2., &kline), 197.);
+        assert_eq!(model.fill_price(&OrderSide::Buy, 200., 0., &kline), 200.);
+        let empty = SlippageModel::OrderBook(OrderBookSnapshot::default());
+        assert_eq!(empty.fill_price(&OrderSide::Buy, 200., 1., &kline), 200.);
+    }
+
+    #[test]
+    fn funding_marks_fall_on_00_08_16_utc() {
+        let marks = |from: i64, to: i64| funding_marks(from, to).collect::<Vec<_>>();
+        let day = [DAY_START, DAY_START + 8 * HOUR_MS, DAY_START + 16 * HOUR_MS];
+        assert_eq!(marks(DAY_START, DAY_START + 24 * HOUR_MS - 1), day);
+        // Both ends are inclusive
+        assert_eq!(
+            marks(DAY_START + 8 * HOUR_MS, DAY_START + 16 * HOUR_MS),
+            day[1..]
+        );
+        assert_eq!(marks(DAY_START + 1, DAY_START + 16 * HOUR_MS - 1), [day[1]]);
+        // Across midnight
+        assert_eq!(
+            marks(DAY_START - 8 * HOUR_MS + 1, DAY_START + 8 * HOUR_MS - 1),
+            [DAY_START]
+        );
+        assert!(marks(DAY_START + 1, DAY_START + 8 * HOUR_MS - 1).is_empty());
+        assert!(marks(DAY_START + 8 * HOUR_MS, DAY_START).is_empty());
+        // Before the epoch
+        assert_eq!(marks(-8 * HOUR_MS - 1, 1), [-8 * HOUR_MS, 0]);
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use crate::backtest::costs::FundingModel;
+use crate::clients::mongo_client::MongoClient;
+use crate::types::config::Config;
+use crate::types::funding::FundingRate;
+use crate::types::kline::Kline;
+
+/// Klines to backtest the single configured symbol on: from
+/// `backtest.csv_path` if set, else from MongoDB between `backtest.from_ts`
+/// and `backtest.to_ts`.
+pub async fn load_klines(config: &Config) -> Result<Vec<Kline>> {
+    if let Some(csv_path) = &config.backtest.csv_path {
+        return read_klines_csv(csv_path);
+    }
+    let symbol = config.backtest_symbol()?;
+    let mongo_config = config
+        .mongo
+        .as_ref()
+        .ok_or_else(|| anyhow!("Config needs \"backtest.csv_path\" or a \"mongo\" section"))?;
+    let mongo_client = MongoClient::new(&mongo_config.connection_string.resolve()?).await?;
+    let klines = mongo_client
+        .get_klines(
+            &mongo_config.database,
+            &mongo_config.kline_collection(symbol, config.interval),
+            config.backtest.from_ts.unwrap_or(0),
+            config.backtest.to_ts,
+        )
+        .await?;
+    Ok(klines)
+}
+
+/// Funding rates for `FundingModel::History`: from `backtest.funding_csv_path`
+/// if set, else from the MongoDB funding collection. Empty with another
+/// funding model.
+pub async fn load_funding_rates(config: &Config) -> Result<Vec<FundingRate>> {
+    if config.backtest.config.exchange.funding != FundingModel::History {
+        return Ok(Vec::new());
+    }
+    if let Some(funding_csv_path) = &config.backtest.funding_csv_path {
+        return read_funding_csv(funding_csv_path);
+    }
+    let symbol = config.backtest_symbol()?;
+    let mongo_config = config.mongo.as_ref().ok_or_else(|| {
+        anyhow!("Funding history needs \"backtest.funding_csv_path\" or a \"mongo\" section")
+    })?;
+    let mongo_client = MongoClient::new(&mongo_config.connection_string.resolve()?).await?;
+    let rates = mongo_client
+        .get_funding_rates(
+            &mongo_config.database,
+            &mongo_config.funding_collection(symbol),
+            config.backtest.from_ts.unwrap_or(0),
+            config.backtest.to_ts,
+        )
+        .await?;
+    Ok(rates)
+}
+
+/// Read klines from a csv file whose header matches the `Kline` fields,
+/// i.e. `open_timestamp,close_timestamp,open,high,low,close` and optionally
+/// the volume columns.
+pub fn read_klines_csv(path: &Path) -> Result<Vec<Kline>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut klines = Vec::new();
+    for record in reader.deserialize() {
+        let kline: Kline = record?;
+        klines.push(kline);
+    }
+    klines.sort_by_key(|k| k.close_timestamp);
+    Ok(klines)
+}
+
+/// Read funding rates from a csv file with a `symbol,funding_time,rate`
+/// header.
+pub fn read_funding_csv(path: &Path) -> Result<Vec<FundingRate>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut rates = Vec::new();
+    for record in reader.deserialize() {
+        let rate: FundingRate = record?;
+        rates.push(rate);
+    }
+    rates.sort_by_key(|r| r.funding_time);
+    Ok(rates)
+}
@@ -0,0 +1,288 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::exchange::{ExchangeConfig, SimulatedExchange};
+use crate::strategy::base::Strategy;
+use crate::strategy::context::{OrderRequest, StrategyContext};
+use crate::types::funding::FundingRate;
+use crate::types::kline::Kline;
+use crate::types::order::Fill;
+use crate::types::timer::{FixedUpdate, SimulatedClock, Timer};
+use crate::types::trade::Trade;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BacktestConfig {
+    #[serde(default)]
+    pub exchange: ExchangeConfig,
+    pub fixed_update: Option<FixedUpdate>, // drives `Strategy::on_timer`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EquityPoint {
+    pub ts: i64,
+    pub equity: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BacktestResult {
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+pub struct Backtester {
+    config: BacktestConfig,
+    exchange: SimulatedExchange,
+    equity_curve: Vec<EquityPoint>,
+}
+
+impl Backtester {
+    pub fn new(config: BacktestConfig) -> Backtester {
+        Backtester {
+            exchange: SimulatedExchange::new(config.exchange.clone()),
+            config,
+            equity_curve: Vec::new(),
+        }
+    }
+
+    /// Funding rate history for `FundingModel::History`, e.g. from
+    /// `MongoClient::get_funding_rates`.
+    pub fn with_funding_rates(mut self, funding_rates: Vec<FundingRate>) -> Self {
+        self.exchange = self.exchange.with_funding_rates(funding_rates);
+        self
+    }
+
+    /// Replay the klines bar by bar into the strategy. Orders submitted while
+    /// handling a kline fill at the open of the next one. Funding settles on
+    /// the trades open at the start of the kline. Trades still open after the
+    /// last kline are closed at its close price. The equity curve starts with
+    /// the initial balance at the first kline's open.
+    pub fn run<S: Strategy>(mut self, klines: &[Kline], strategy: &mut S) -> BacktestResult {
+        let first = match klines.first() {
+            Some(kline) => kline,
+            None => return BacktestResult::default(),
+        };
+        self.equity_curve.push(EquityPoint {
+            ts: first.open_timestamp,
+            equity: self.exchange.balance(),
+        });
+        let mut ctx = self.context(first.open, first.open_timestamp);
+        strategy.on_start(&mut ctx);
+        let mut pending_orders = ctx.take_orders();
+        // Timer driven by kline time, a kline counts as closed 1ms after its close time
+        let clock = SimulatedClock::new(first.open_timestamp);
+        let mut timer = self
+            .config
+            .fixed_update
+            .clone()
+            .map(|fixed_update| Timer::with_clock(fixed_update, clock.clone()));
+
+        for kline in klines.iter() {
+            self.exchange.apply_funding(kline);
+            let mut fills = Vec::new();
+            for request in pending_orders.drain(..) {
+                fills.extend(self.exchange.fill_order(request, kline));
+            }
+            fills.extend(self.exchange.check_exits(kline));
+            pending_orders.extend(self.notify_fills(strategy, &fills, kline));
+
+            self.equity_curve.push(EquityPoint {
+                ts: kline.close_timestamp,
+                equity: self.exchange.equity(kline.close),
+            });
+
+            let mut ctx = self.context(kline.close, kline.close_timestamp);
+            strategy.on_kline(kline, &mut ctx);
+            clock.set(kline.close_timestamp + 1);
+            if let Some(timer) = timer.as_mut() {
+                if timer.update() {
+                    strategy.on_timer(timer.get_ts_ms(), &mut ctx);
+                }
+            }
+            pending_orders.extend(ctx.take_orders());
+        }
+
+        let last = klines.last().unwrap();
+        let fills = self.exchange.close_all(last);
+        self.notify_fills(strategy, &fills, last);
+        if let Some(point) = self.equity_curve.last_mut() {
+            point.equity = self.exchange.balance();
+        }
+        let mut ctx = self.context(last.close, last.close_timestamp);
+        strategy.on_stop(&mut ctx);
+
+        info!(
+            "Backtest finished, trades: {}, balance: {}",
+            self.exchange.closed_trades().len(),
+            self.exchange.balance()
+        );
+        BacktestResult {
+            trades: self.exchange.into_closed_trades(),
+            equity_curve: self.equity_curve,
+        }
+    }
+
+    fn context(&self, price: f64, ts: i64) -> StrategyContext {
+        StrategyContext::new(
+            ts,
+            self.exchange.account(price, ts),
+            self.exchange.open_trades().to_vec(),
+        )
+    }
+
+    fn notify_fills<S: Strategy>(
+        &self,
+        strategy: &mut S,
+        fills: &[Fill],
+        kline: &Kline,
+    ) -> Vec<OrderRequest> {
+        let mut orders = Vec::new();
+        for fill in fills.iter() {
+            let mut ctx = self.context(kline.close, fill.ts);
+            strategy.on_fill(fill, &mut ctx);
+            orders.extend(ctx.take_orders());
+        }
+        orders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::types::order::{Order, OrderSide};
+    use crate::types::trade::TradeSide;
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    fn kline(hour: i64, open: f64, high: f64, low: f64, close: f64) -> Kline {
+        Kline {
+            open_timestamp: hour * HOUR_MS,
+            close_timestamp: (hour + 1) * HOUR_MS - 1,
+            open,
+            high,
+            low,
+            close,
+            ..Default::default()
+        }
+    }
+
+    fn klines() -> Vec<Kline> {
+        vec![
+            kline(0, 100., 101., 99., 100.),
+            kline(1, 100., 106., 99., 105.),
+            kline(2, 105., 112., 104., 110.),
+            kline(3, 110., 111., 100., 102.),
+        ]
+    }
+
+    fn request(order_side: OrderSide, size: f64, tp_price: f64, sl_price: f64) -> OrderRequest {
+        OrderRequest {
+            order: Order::market_order("BTCUSDT".to_owned(), order_side, size),
+            tp_price,
+            sl_price,
+        }
+    }
+
+    /// Places the given orders after the kline at each index.
+    #[derive(Default)]
+    struct Script {
+        orders: HashMap<usize, Vec<OrderRequest>>,
+        klines_seen: usize,
+        fills: Vec<Fill>,
+    }
+
+    impl Strategy for Script {
+        fn on_kline(&mut self, _kline: &Kline, ctx: &mut StrategyContext) {
+            for request in self.orders.remove(&self.klines_seen).unwrap_or_default() {
+                ctx.place_order_with_exits(request.order, request.tp_price, request.sl_price);
+            }
+            self.klines_seen += 1;
+        }
+
+        fn on_fill(&mut self, fill: &Fill, _ctx: &mut StrategyContext) {
+            self.fills.push(fill.clone());
+        }
+    }
+
+    fn backtester(fee_rate: f64) -> Backtester {
+        Backtester::new(BacktestConfig {
+            exchange: ExchangeConfig {
+                initial_balance: 1000.,
+                fee_rate,
+                ..Default::default()
+            },
+            fixed_update: None,
+        })
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn fills_at_next_open_and_exits_at_take_profit() {
+        let mut strategy = Script {
+            orders: HashMap::from([(0, vec![request(OrderSide::Buy, 1., 111., 95.)])]),
+            ..Default::default()
+        };
+        let result = backtester(0.001).run(&klines(), &mut strategy);
+
+        assert_eq!(strategy.fills.len(), 2);
+        let (entry, exit) = (&strategy.fills[0], &strategy.fills[1]);
+        assert_eq!((entry.price, entry.ts), (100., HOUR_MS));
+        assert_close(entry.fee, 0.1);
+        assert_eq!(exit.order_side, OrderSide::Sell);
+        assert_eq!((exit.price, exit.ts), (111., 3 * HOUR_MS - 1));
+        assert_close(exit.fee, 0.111);
+
+        assert_eq!(result.trades.len(), 1);
+        assert_close(result.trades[0].realized_pnl(), 11. - 0.211);
+        let curve: Vec<_> = result
+            .equity_curve
+            .iter()
+            .map(|point| (point.ts, point.equity))
+            .collect();
+        let expected = [
+            (0, 1000.),
+            (HOUR_MS - 1, 1000.),
+            (2 * HOUR_MS - 1, 1004.9),
+            (3 * HOUR_MS - 1, 1010.789),
+            (4 * HOUR_MS - 1, 1010.789),
+        ];
+        assert_eq!(curve.len(), expected.len());
+        for ((ts, equity), (expected_ts, expected_equity)) in curve.iter().zip(expected) {
+            assert_eq!(*ts, expected_ts);
+            assert_close(*equity, expected_equity);
+        }
+    }
+
+    #[test]
+    fn nets_opposite_orders_and_closes_at_the_end() {
+        let mut strategy = Script {
+            orders: HashMap::from([
+                (0, vec![request(OrderSide::Buy, 2., 0., 0.)]),
+                (1, vec![request(OrderSide::Sell, 3., 0., 0.)]),
+            ]),
+            ..Default::default()
+        };
+        let result = backtester(0.).run(&klines(), &mut strategy);
+
+        // The sell closes the long at 105 and opens a 1 short, closed at the last close
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].entry_side, TradeSide::Buy);
+        assert_close(result.trades[0].realized_pnl(), 10.);
+        assert_eq!(result.trades[1].entry_side, TradeSide::Sell);
+        assert_eq!(result.trades[1].position, 1.);
+        assert_close(result.trades[1].realized_pnl(), 3.);
+        assert_eq!(result.equity_curve.first().unwrap().equity, 1000.);
+        assert_close(result.equity_curve.last().unwrap().equity, 1013.);
+    }
+
+    #[test]
+    fn no_klines_gives_an_empty_result() {
+        let result = backtester(0.).run(&[], &mut Script::default());
+        assert!(result.trades.is_empty());
+        assert!(result.equity_curve.is_empty());
+    }
+}
@@ -0,0 +1,529 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::backtest::costs::{funding_marks, FeeModel, FundingModel, Liquidity, SlippageModel};
+use crate::strategy::context::OrderRequest;
+use crate::types::account::{Account, Asset, Position};
+use crate::types::funding::FundingRate;
+use crate::types::kline::Kline;
+use crate::types::order::{Fill, OrderSide};
+use crate::types::trade::{ExitReason, IntrabarOrder, Trade, TradeSide};
+
+/// Every field falls back to `ExchangeConfig::default`, so a config only
+/// sets what it changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExchangeConfig {
+    pub initial_balance: f64,
+    pub fee_rate: f64, // charged on the notional of every fill without a fee model
+    pub leverage: f64,
+    pub intrabar_order: IntrabarOrder, // when a kline touches both tp and sl
+    pub fees: Option<FeeModel>,        // replaces `fee_rate`
+    pub slippage: SlippageModel,
+    pub funding: FundingModel,
+}
+
+impl ExchangeConfig {
+    pub fn fee_rate(&self, liquidity: Liquidity) -> f64 {
+        match &self.fees {
+            Some(fees) => fees.rate(liquidity),
+            None => self.fee_rate,
+        }
+    }
+}
+
+impl Default for ExchangeConfig {
+    fn default() -> Self {
+        ExchangeConfig {
+            initial_balance: 10000.,
+            fee_rate: 0.0004,
+            leverage: 1.,
+            intrabar_order: IntrabarOrder::default(),
+            fees: None,
+            slippage: SlippageModel::default(),
+            funding: FundingModel::default(),
+        }
+    }
+}
+
+/// Simulated futures account used by the backtester and for paper trading.
+/// Market orders fill at the kline's open, take profit and stop loss are
+/// checked against each kline. Market orders and stop losses pay the taker
+/// fee and slippage, take profits the maker fee.
+pub struct SimulatedExchange {
+    config: ExchangeConfig,
+    balance: f64,
+    open_trades: Vec<Trade>,
+    closed_trades: Vec<Trade>,
+    funding_rates: HashMap<String, BTreeMap<i64, f64>>, // symbol -> funding time -> rate
+    missing_funding: HashSet<String>, // symbols warned about having no funding history
+}
+
+impl SimulatedExchange {
+    pub fn new(config: ExchangeConfig) -> SimulatedExchange {
+        SimulatedExchange {
+            balance: config.initial_balance,
+            config,
+            open_trades: Vec::new(),
+            closed_trades: Vec::new(),
+            funding_rates: HashMap::new(),
+            missing_funding: HashSet::new(),
+        }
+    }
+
+    /// Rate history for `FundingModel::History`.
+    pub fn with_funding_rates(mut self, funding_rates: Vec<FundingRate>) -> Self {
+        for rate in funding_rates {
+            self.funding_rates
+                .entry(rate.symbol)
+                .or_default()
+                .insert(rate.funding_time, rate.rate);
+        }
+        self
+    }
+
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    pub fn open_trades(&self) -> &[Trade] {
+        &self.open_trades
+    }
+
+    pub fn closed_trades(&self) -> &[Trade] {
+        &self.closed_trades
+    }
+
+    pub fn into_closed_trades(self) -> Vec<Trade> {
+        self.closed_trades
+    }
+
+    pub fn equity(&self, price: f64) -> f64 {
+        let unrealized: f64 = self.open_trades.iter().map(|t| t.gross_pnl(price)).sum();
+        self.balance + unrealized
+    }
+
+    fn used_margin(&self) -> f64 {
+        self.open_trades
+            .iter()
+            .map(|t| t.entry_price * t.position / self.config.leverage)
+            .sum()
+    }
+
+    /// Snapshot in the same shape `BinanceFuturesApiClient::get_account` returns.
+    pub fn account(&self, price: f64, ts: i64) -> Account {
+        let mut account = Account {
+            assets: vec![Asset {
+                asset: "USDT".to_owned(),
+                wallet_balance: self.balance,
+                available_balance: self.equity(price) - self.used_margin(),
+                update_timestamp: ts,
+            }],
+            ..Default::default()
+        };
+        for trade in self.open_trades.iter() {
+            let position_amt = trade.position * trade.entry_side.value();
+            match account
+                .positions
+                .iter_mut()
+                .find(|p| p.symbol == trade.symbol)
+            {
+                Some(position) => {
+                    let total = position.position_amt + position_amt;
+                    if total != 0. {
+                        position.entry_price = (position.entry_price * position.position_amt
+                            + trade.entry_price * position_amt)
+                            / total;
+                    }
+                    position.position_amt = total;
+                    position.unrealized_profit += (price - trade.entry_price) * position_amt;
+                }
+                None => account.positions.push(Position {
+                    symbol: trade.symbol.clone(),
+                    unrealized_profit: (price - trade.entry_price) * position_amt,
+                    leverage: self.config.leverage as u64,
+                    entry_price: trade.entry_price,
+                    position_side: "BOTH".to_owned(),
+                    position_amt,
+                }),
+            }
+        }
+        account
+    }
+
+    /// Settle funding on the open trades at every funding time within the
+    /// kline, valued at its open. Returns the net payment received.
+    pub fn apply_funding(&mut self, kline: &Kline) -> f64 {
+        let mut total = 0.;
+        for trade in self.open_trades.iter_mut() {
+            let rates = match &self.config.funding {
+                FundingModel::None => Vec::new(),
+                FundingModel::Fixed { rate } => {
+                    funding_marks(kline.open_timestamp, kline.close_timestamp)
+                        .map(|_| *rate)
+                        .collect()
+                }
+                FundingModel::History => match self.funding_rates.get(&trade.symbol) {
+                    Some(rates) => rates
+                        .range(kline.open_timestamp..=kline.close_timestamp)
+                        .map(|(_, rate)| *rate)
+                        .collect(),
+                    None => {
+                        if self.missing_funding.insert(trade.symbol.clone()) {
+                            warn!(
+                                "No funding history for {}, its trades pay no funding",
+                                trade.symbol
+                            );
+                        }
+                        Vec::new()
+                    }
+                },
+            };
+            for rate in rates {
+                total += trade.apply_funding(rate, kline.open);
+            }
+        }
+        self.balance += total;
+        total
+    }
+
+    /// Net the order against opposite open trades first (FIFO), the remainder
+    /// opens a new trade unless the order is reduce only. Fills at the
+    /// kline's open plus slippage.
+    pub fn fill_order(&mut self, request: OrderRequest, kline: &Kline) -> Option<Fill> {
+        let order = request.order;
+        let ts = kline.open_timestamp;
+        let price =
+            self.config
+                .slippage
+                .fill_price(&order.order_side, kline.open, order.size, kline);
+        let fee_rate = self.config.fee_rate(Liquidity::Taker);
+        let side = match order.order_side {
+            OrderSide::Buy => TradeSide::Buy,
+            OrderSide::Sell => TradeSide::Sell,
+        };
+        let mut fill = Fill {
+            symbol: order.symbol.clone(),
+            order_side: order.order_side.clone(),
+            size: 0.,
+            price,
+            fee: 0.,
+            ts,
+        };
+        let mut remaining = order.size;
+        let mut still_open = Vec::new();
+        let open_trades = std::mem::take(&mut self.open_trades);
+        for mut trade in open_trades {
+            if remaining <= 0. || trade.symbol != order.symbol || trade.entry_side == side {
+                still_open.push(trade);
+                continue;
+            }
+            if trade.position <= remaining {
+                remaining -= trade.position;
+                fill.size += trade.position;
+                fill.fee += self.close_trade(trade, price, ts, fee_rate);
+            } else {
+                let fee = price * remaining * fee_rate;
+                let closed = trade.close_partial(remaining, price, ts, fee);
+                self.balance += closed.gross_pnl(price) - fee;
+                self.closed_trades.push(closed);
+                fill.size += remaining;
+                fill.fee += fee;
+                remaining = 0.;
+                still_open.push(trade);
+            }
+        }
+        self.open_trades = still_open;
+
+        if remaining > 0. && !order.reduce_only {
+            let notional = price * remaining;
+            let fee = notional * fee_rate;
+            let available = self.equity(price) - self.used_margin();
+            if notional / self.config.leverage + fee > available {
+                warn!(
+                    "Insufficient margin for {:?} {} {} at {}, available: {}",
+                    order.order_side, remaining, order.symbol, price, available
+                );
+            } else {
+                self.balance -= fee;
+                fill.size += remaining;
+                fill.fee += fee;
+                let mut trade = Trade::open(order.symbol, side, remaining, price, ts, fee);
+                trade.tp_price = request.tp_price;
+                trade.sl_price = request.sl_price;
+                self.open_trades.push(trade);
+            }
+        }
+        if fill.size > 0. {
+            Some(fill)
+        } else {
+            None
+        }
+    }
+
+    /// Close trades whose take profit or stop loss was touched by the kline.
+    /// If both are inside the range, `intrabar_order` decides which is hit.
+    pub fn check_exits(&mut self, kline: &Kline) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        let open_trades = std::mem::take(&mut self.open_trades);
+        for trade in open_trades {
+            match trade.exit_hit(kline, self.config.intrabar_order) {
+                Some((ExitReason::TakeProfit, price)) => fills.push(self.close_with_fill(
+                    trade,
+                    price,
+                    kline,
+                    kline.close_timestamp,
+                    Liquidity::Maker,
+                )),
+                Some((ExitReason::StopLoss, price)) => fills.push(self.close_with_fill(
+                    trade,
+                    price,
+                    kline,
+                    kline.close_timestamp,
+                    Liquidity::Taker,
+                )),
+                None => self.open_trades.push(trade),
+            }
+        }
+        fills
+    }
+
+    /// Close every trade at the kline's close.
+    pub fn close_all(&mut self, kline: &Kline) -> Vec<Fill> {
+        let open_trades = std::mem::take(&mut self.open_trades);
+        open_trades
+            .into_iter()
+            .map(|trade| {
+                self.close_with_fill(
+                    trade,
+                    kline.close,
+                    kline,
+                    kline.close_timestamp,
+                    Liquidity::Taker,
+                )
+            })
+            .collect()
+    }
+
+    /// Taker exits pay slippage on top of the taker fee.
+    fn close_with_fill(
+        &mut self,
+        trade: Trade,
+        price: f64,
+        kline: &Kline,
+        ts: i64,
+        liquidity: Liquidity,
+    ) -> Fill {
+        let symbol = trade.symbol.clone();
+        let size = trade.position;
+        let order_side = match trade.entry_side {
+            TradeSide::Buy => OrderSide::Sell,
+            _ => OrderSide::Buy,
+        };
+        let price = match liquidity {
+            Liquidity::Maker => price,
+            Liquidity::Taker => self
+                .config
+                .slippage
+                .fill_price(&order_side, price, size, kline),
+        };
+        let fee_rate = self.config.fee_rate(liquidity);
+        let fee = self.close_trade(trade, price, ts, fee_rate);
+        Fill {
+            symbol,
+            order_side,
+            size,
+            price,
+            fee,
+            ts,
+        }
+    }
+
+    /// Returns the exit fee.
+    fn close_trade(&mut self, mut trade: Trade, price: f64, ts: i64, fee_rate: f64) -> f64 {
+        let fee = price * trade.position * fee_rate;
+        self.balance += trade.gross_pnl(price) - fee;
+        trade.close(price, ts, fee);
+        self.closed_trades.push(trade);
+        fee
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::order::Order;
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    fn kline(hour: i64, open: f64, high: f64, low: f64, close: f64) -> Kline {
+        Kline {
+            open_timestamp: hour * HOUR_MS,
+            close_timestamp: (hour + 1) * HOUR_MS - 1,
+            open,
+            high,
+            low,
+            close,
+            ..Default::default()
+        }
+    }
+
+    fn exchange(initial_balance: f64, leverage: f64) -> SimulatedExchange {
+        SimulatedExchange::new(ExchangeConfig {
+            initial_balance,
+            fee_rate: 0.001,
+            leverage,
+            ..Default::default()
+        })
+    }
+
+    fn request(order_side: OrderSide, size: f64) -> OrderRequest {
+        OrderRequest {
+            order: Order::market_order("BTCUSDT".to_owned(), order_side, size),
+            tp_price: 0.,
+            sl_price: 0.,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn partial_close_keeps_the_rest_open() {
+        let mut exchange = exchange(1000., 1.);
+        let bar = kline(0, 100., 101., 99., 100.);
+        exchange.fill_order(request(OrderSide::Buy, 2.), &bar);
+        assert_close(exchange.balance(), 1000. - 0.2);
+
+        let bar = kline(1, 110., 111., 109., 110.);
+        let fill = exchange
+            .fill_order(request(OrderSide::Sell, 0.5), &bar)
+            .unwrap();
+        assert_eq!((fill.size, fill.price), (0.5, 110.));
+        assert_close(fill.fee, 0.055);
+        assert_eq!(exchange.open_trades().len(), 1);
+        assert_eq!(exchange.open_trades()[0].position, 1.5);
+        assert_close(exchange.open_trades()[0].fee, 0.15);
+        let closed = &exchange.closed_trades()[0];
+        assert_eq!(closed.position, 0.5);
+        // The closed part carries a quarter of the entry fee and the exit fee
+        assert_close(closed.fee, 0.05 + 0.055);
+        assert_close(exchange.balance(), 1000. - 0.2 + 5. - 0.055);
+
+        let account = exchange.account(120., bar.close_timestamp);
+        assert_eq!(account.positions[0].position_amt, 1.5);
+        assert_close(account.positions[0].unrealized_profit, 30.);
+    }
+
+    #[test]
+    fn reduce_only_never_opens() {
+        let mut exchange = exchange(1000., 1.);
+        let mut reduce = request(OrderSide::Sell, 1.);
+        reduce.order.reduce_only = true;
+        let bar = kline(0, 100., 101., 99., 100.);
+        assert!(exchange.fill_order(reduce.clone(), &bar).is_none());
+
+        exchange.fill_order(request(OrderSide::Buy, 0.5), &bar);
+        let fill = exchange.fill_order(reduce, &bar).unwrap();
+        assert_eq!(fill.size, 0.5);
+        assert!(exchange.open_trades().is_empty());
+    }
+
+    #[test]
+    fn rejects_orders_above_the_available_margin() {
+        let mut exchange = exchange(100., 2.);
+        let bar = kline(0, 100., 101., 99., 100.);
+        // 300 notional needs 150 margin at 2x
+        assert!(exchange
+            .fill_order(request(OrderSide::Buy, 3.), &bar)
+            .is_none());
+        assert_eq!(exchange.balance(), 100.);
+
+        // 190 notional needs 95 margin plus a 0.19 fee
+        assert!(exchange
+            .fill_order(request(OrderSide::Buy, 1.9), &bar)
+            .is_some());
+        let account = exchange.account(100., 0);
+        assert_close(account.assets[0].available_balance, 100. - 0.19 - 95.);
+        assert!(exchange
+            .fill_order(request(OrderSide::Buy, 0.1), &bar)
+            .is_none());
+    }
+
+    #[test]
+    fn stop_loss_exits_at_the_gap_open_with_taker_fee() {
+        let mut exchange = SimulatedExchange::new(ExchangeConfig {
+            initial_balance: 1000.,
+            fee_rate: 0.,
+            fees: Some(FeeModel {
+                maker_rate: Some(0.0002),
+                taker_rate: Some(0.0005),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mut long = request(OrderSide::Buy, 1.);
+        long.sl_price = 95.;
+        long.tp_price = 110.;
+        exchange.fill_order(long, &kline(0, 100., 101., 99., 100.));
+        assert!(exchange
+            .check_exits(&kline(1, 100., 101., 96., 97.))
+            .is_empty());
+
+        // Gaps below the stop, exits at the open
+        let fills = exchange.check_exits(&kline(2, 90., 92., 88., 91.));
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 90.);
+        assert_close(fills[0].fee, 90. * 0.0005);
+        let trade = &exchange.closed_trades()[0];
+        assert_eq!(trade.exit_ts, 3 * HOUR_MS - 1);
+        assert_close(trade.realized_pnl(), -10. - 100. * 0.0005 - 90. * 0.0005);
+    }
+
+    #[test]
+    fn take_profit_pays_the_maker_fee() {
+        let mut exchange = SimulatedExchange::new(ExchangeConfig {
+            initial_balance: 1000.,
+            fee_rate: 0.,
+            fees: Some(FeeModel {
+                maker_rate: Some(0.0002),
+                taker_rate: Some(0.0005),
+                ..Default::default()
+            }),
+            slippage: SlippageModel::FixedBps { bps: 10. },
+            ..Default::default()
+        });
+        let mut short = request(OrderSide::Sell, 1.);
+        short.tp_price = 90.;
+        exchange.fill_order(short, &kline(0, 100., 101., 99., 100.));
+        // Sells fill 10bps below the open
+        assert_eq!(exchange.open_trades()[0].entry_price, 99.9);
+
+        let fills = exchange.check_exits(&kline(1, 95., 96., 89., 92.));
+        assert_eq!(fills[0].order_side, OrderSide::Buy);
+        assert_eq!(fills[0].price, 90.);
+        assert_close(fills[0].fee, 90. * 0.0002);
+    }
+
+    #[test]
+    fn fixed_funding_settles_at_the_8h_marks() {
+        let mut exchange = SimulatedExchange::new(ExchangeConfig {
+            initial_balance: 1000.,
+            fee_rate: 0.,
+            funding: FundingModel::Fixed { rate: 0.0001 },
+            ..Default::default()
+        });
+        exchange.fill_order(
+            request(OrderSide::Buy, 2.),
+            &kline(6, 100., 101., 99., 100.),
+        );
+        assert_eq!(exchange.apply_funding(&kline(7, 100., 101., 99., 100.)), 0.);
+        // The 08:00 kline contains the mark, the long pays
+        let paid = exchange.apply_funding(&kline(8, 100., 101., 99., 100.));
+        assert_close(paid, -0.02);
+        assert_close(exchange.balance(), 999.98);
+        assert_close(exchange.open_trades()[0].funding, -0.02);
+    }
+}
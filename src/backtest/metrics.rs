@@ -0,0 +1,328 @@
+use crate::backtest::engine::EquityPoint;
+use crate::types::trade::Trade;
+
+const YEAR_MS: f64 = 365. * 24. * 60. * 60. * 1000.;
+// Deviations below it are float noise of constant returns, not risk
+const MIN_DEVIATION: f64 = 1e-12;
+
+pub fn net_profit(equity_curve: &[EquityPoint]) -> f64 {
+    match (equity_curve.first(), equity_curve.last()) {
+        (Some(first), Some(last)) => last.equity - first.equity,
+        _ => 0.,
+    }
+}
+
+/// Largest peak to trough decline as a fraction of the peak.
+pub fn max_drawdown(equity_curve: &[EquityPoint]) -> f64 {
+    let mut peak = f64::MIN;
+    let mut max_drawdown = 0.;
+    for point in equity_curve.iter() {
+        peak = peak.max(point.equity);
+        if peak > 0. {
+            max_drawdown = f64::max(max_drawdown, (peak - point.equity) / peak);
+        }
+    }
+    max_drawdown
+}
+
+/// Final equity over the initial one, minus 1.
+pub fn total_return(equity_curve: &[EquityPoint]) -> f64 {
+    match (equity_curve.first(), equity_curve.last()) {
+        (Some(first), Some(last)) if first.equity > 0. => last.equity / first.equity - 1.,
+        _ => 0.,
+    }
+}
+
+/// Longest time spent below a previous peak, until it's recovered or the
+/// curve ends.
+pub fn max_drawdown_duration_ms(equity_curve: &[EquityPoint]) -> i64 {
+    let mut peak = match equity_curve.first() {
+        Some(first) => first,
+        None => return 0,
+    };
+    let mut max_duration = 0;
+    for point in equity_curve.iter() {
+        if point.equity >= peak.equity {
+            peak = point;
+        }
+        max_duration = max_duration.max(point.ts - peak.ts);
+    }
+    max_duration
+}
+
+/// Drawdown from the running peak at every point, as a fraction of the peak.
+pub fn drawdown_curve(equity_curve: &[EquityPoint]) -> Vec<EquityPoint> {
+    let mut peak = f64::MIN;
+    equity_curve
+        .iter()
+        .map(|point| {
+            peak = peak.max(point.equity);
+            EquityPoint {
+                ts: point.ts,
+                equity: if peak > 0. {
+                    (point.equity - peak) / peak
+                } else {
+                    0.
+                },
+            }
+        })
+        .collect()
+}
+
+pub fn annualized_return(equity_curve: &[EquityPoint]) -> f64 {
+    let (first, last) = match (equity_curve.first(), equity_curve.last()) {
+        (Some(first), Some(last)) if first.equity > 0. && last.ts > first.ts => (first, last),
+        _ => return 0.,
+    };
+    let years = (last.ts - first.ts) as f64 / YEAR_MS;
+    (last.equity / first.equity).max(0.).powf(1. / years) - 1.
+}
+
+/// Annualized Sharpe ratio of the per bar returns with a zero risk free rate.
+pub fn sharpe_ratio(equity_curve: &[EquityPoint]) -> f64 {
+    let returns = bar_returns(equity_curve);
+    if returns.len() < 2 {
+        return 0.;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance =
+        returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    if variance.sqrt() < MIN_DEVIATION {
+        return 0.;
+    }
+    mean / variance.sqrt() * bars_per_year(equity_curve).sqrt()
+}
+
+/// Like the Sharpe ratio with only the returns below zero counted as risk.
+pub fn sortino_ratio(equity_curve: &[EquityPoint]) -> f64 {
+    let returns = bar_returns(equity_curve);
+    if returns.len() < 2 {
+        return 0.;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let downside = returns.iter().map(|r| r.min(0.).powi(2)).sum::<f64>() / returns.len() as f64;
+    if downside.sqrt() < MIN_DEVIATION {
+        return 0.;
+    }
+    mean / downside.sqrt() * bars_per_year(equity_curve).sqrt()
+}
+
+pub fn calmar_ratio(equity_curve: &[EquityPoint]) -> f64 {
+    let max_drawdown = max_drawdown(equity_curve);
+    if max_drawdown == 0. {
+        return 0.;
+    }
+    annualized_return(equity_curve) / max_drawdown
+}
+
+pub fn bar_returns(equity_curve: &[EquityPoint]) -> Vec<f64> {
+    equity_curve
+        .windows(2)
+        .filter(|w| w[0].equity != 0.)
+        .map(|w| w[1].equity / w[0].equity - 1.)
+        .collect()
+}
+
+pub fn bars_per_year(equity_curve: &[EquityPoint]) -> f64 {
+    match (equity_curve.first(), equity_curve.last()) {
+        (Some(first), Some(last)) if last.ts > first.ts => {
+            let bar_ms = (last.ts - first.ts) as f64 / (equity_curve.len() - 1) as f64;
+            YEAR_MS / bar_ms
+        }
+        _ => 0.,
+    }
+}
+
+/// Fraction of closed trades with a positive net PnL.
+pub fn win_rate(trades: &[Trade]) -> f64 {
+    if trades.is_empty() {
+        return 0.;
+    }
+    trades.iter().filter(|t| t.realized_pnl() > 0.).count() as f64 / trades.len() as f64
+}
+
+/// Gross profit over gross loss, none without losing trades.
+pub fn profit_factor(trades: &[Trade]) -> Option<f64> {
+    let profit: f64 = trades.iter().map(|t| t.realized_pnl().max(0.)).sum();
+    let loss: f64 = trades.iter().map(|t| (-t.realized_pnl()).max(0.)).sum();
+    (loss > 0.).then(|| profit / loss)
+}
+
+/// Average net PnL per trade.
+pub fn expectancy(trades: &[Trade]) -> f64 {
+    if trades.is_empty() {
+        return 0.;
+    }
+    trades.iter().map(|t| t.realized_pnl()).sum::<f64>() / trades.len() as f64
+}
+
+pub fn average_win(trades: &[Trade]) -> f64 {
+    mean(
+        trades
+            .iter()
+            .map(|t| t.realized_pnl())
+            .filter(|pnl| *pnl > 0.),
+    )
+}
+
+/// Average losing trade, negative.
+pub fn average_loss(trades: &[Trade]) -> f64 {
+    mean(
+        trades
+            .iter()
+            .map(|t| t.realized_pnl())
+            .filter(|pnl| *pnl < 0.),
+    )
+}
+
+/// Most consecutive losing trades in exit order.
+pub fn longest_losing_streak(trades: &[Trade]) -> usize {
+    let mut sorted = trades.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|t| t.exit_ts);
+    let (mut streak, mut longest) = (0, 0);
+    for trade in sorted {
+        if trade.realized_pnl() < 0. {
+            streak += 1;
+            longest = longest.max(streak);
+        } else {
+            streak = 0;
+        }
+    }
+    longest
+}
+
+/// Fraction of the equity curve's time span with at least one trade open.
+pub fn exposure_time(trades: &[Trade], equity_curve: &[EquityPoint]) -> f64 {
+    let (start, end) = match (equity_curve.first(), equity_curve.last()) {
+        (Some(first), Some(last)) if last.ts > first.ts => (first.ts, last.ts),
+        _ => return 0.,
+    };
+    let mut intervals = trades
+        .iter()
+        .map(|t| (t.entry_ts.max(start), t.exit_ts.min(end)))
+        .filter(|(from, to)| to > from)
+        .collect::<Vec<_>>();
+    intervals.sort_unstable();
+    let mut covered = 0;
+    let mut covered_to = start;
+    for (from, to) in intervals {
+        let from = from.max(covered_to);
+        if to > from {
+            covered += to - from;
+            covered_to = to;
+        }
+    }
+    covered as f64 / (end - start) as f64
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0., 0), |(sum, count), v| (sum + v, count + 1));
+    if count == 0 {
+        0.
+    } else {
+        sum / count as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::trade::TradeSide;
+
+    // 5 bars a year, so the annualized return is the total one
+    const BAR_MS: i64 = 73 * 24 * 60 * 60 * 1000;
+
+    fn curve(equities: &[f64]) -> Vec<EquityPoint> {
+        equities
+            .iter()
+            .enumerate()
+            .map(|(i, &equity)| EquityPoint {
+                ts: i as i64 * BAR_MS,
+                equity,
+            })
+            .collect()
+    }
+
+    fn closed(pnl: f64, exit_ts: i64) -> Trade {
+        let mut trade = Trade::open("BTCUSDT".to_owned(), TradeSide::Buy, 1., 100., 0, 0.);
+        trade.close(100. + pnl, exit_ts, 0.);
+        trade
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn curve_metrics_match_reference() {
+        let curve = curve(&[100., 120., 90., 110., 135., 108.]);
+        assert_close(bars_per_year(&curve), 5.);
+        assert_close(total_return(&curve), 0.08);
+        assert_close(annualized_return(&curve), 0.08);
+        // 120 -> 90 is deeper than 135 -> 108
+        assert_close(max_drawdown(&curve), 0.25);
+        assert_eq!(max_drawdown_duration_ms(&curve), 2 * BAR_MS);
+        assert_close(sharpe_ratio(&curve), 0.367630396);
+        assert_close(sortino_ratio(&curve), 0.623117535);
+        assert_close(calmar_ratio(&curve), 0.32);
+        let drawdowns: Vec<_> = drawdown_curve(&curve).iter().map(|p| p.equity).collect();
+        assert_close(drawdowns[2], -0.25);
+        assert_close(drawdowns[5], -0.2);
+    }
+
+    #[test]
+    fn trade_metrics_match_reference() {
+        let trades = [
+            closed(30., 1),
+            closed(-10., 2),
+            closed(-5., 3),
+            closed(20., 4),
+            closed(-10., 5),
+        ];
+        assert_close(profit_factor(&trades).unwrap(), 2.);
+        assert_close(win_rate(&trades), 0.4);
+        assert_close(expectancy(&trades), 5.);
+        assert_close(average_win(&trades), 25.);
+        assert_close(average_loss(&trades), -25. / 3.);
+        assert_eq!(longest_losing_streak(&trades), 2);
+    }
+
+    #[test]
+    fn degenerate_curves_give_zero() {
+        let flat = curve(&[100.; 10]);
+        // Constant returns have a zero deviation up to float noise
+        let growth: Vec<_> = (0..30).map(|i| 100. * 1.01_f64.powi(i)).collect();
+        let growth = curve(&growth);
+        let single = curve(&[100.]);
+        for curve in [&flat, &growth, &single, &Vec::new()] {
+            assert_eq!(sharpe_ratio(curve), 0.);
+            assert_eq!(sortino_ratio(curve), 0.);
+            assert_eq!(calmar_ratio(curve), 0.);
+            assert_eq!(max_drawdown(curve), 0.);
+            assert_eq!(max_drawdown_duration_ms(curve), 0);
+        }
+        for curve in [&flat, &single, &Vec::new()] {
+            assert_eq!(net_profit(curve), 0.);
+            assert_eq!(total_return(curve), 0.);
+            assert_eq!(annualized_return(curve), 0.);
+        }
+        assert_eq!(exposure_time(&[closed(1., 1)], &single), 0.);
+    }
+
+    #[test]
+    fn degenerate_trades_give_zero_or_none() {
+        let winners = [closed(10., 1), closed(5., 2)];
+        assert_eq!(profit_factor(&winners), None);
+        assert_eq!(average_loss(&winners), 0.);
+        assert_eq!(longest_losing_streak(&winners), 0);
+        assert_eq!(profit_factor(&[]), None);
+        assert_eq!(win_rate(&[]), 0.);
+        assert_eq!(expectancy(&[]), 0.);
+        assert_eq!(average_win(&[]), 0.);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod costs;
+pub mod data;
+pub mod engine;
+pub mod exchange;
+pub mod metrics;
+pub mod report;
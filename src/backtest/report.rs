@@ -0,0 +1,233 @@
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::backtest::engine::{BacktestResult, EquityPoint};
+use crate::backtest::metrics;
+use crate::types::trade::Trade;
+
+const CHART_WIDTH: f64 = 900.;
+const CHART_HEIGHT: f64 = 260.;
+const CHART_MAX_POINTS: usize = 2000;
+const DAY_MS: f64 = 24. * 60. * 60. * 1000.;
+
+/// Summary of a backtest. Ratios and returns are fractions, e.g. 0.1 for 10%.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsReport {
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub initial_equity: f64,
+    pub final_equity: f64,
+    pub net_profit: f64,
+    pub total_return: f64,
+    pub annualized_return: f64,
+    pub max_drawdown: f64,
+    pub max_drawdown_duration_ms: i64,
+    pub sharpe_ratio: f64,
+    pub sortino_ratio: f64,
+    pub calmar_ratio: f64,
+    pub trades: usize,
+    pub win_rate: f64,
+    pub profit_factor: Option<f64>, // none without losing trades
+    pub expectancy: f64,
+    pub average_win: f64,
+    pub average_loss: f64,
+    pub exposure_time: f64,
+    pub longest_losing_streak: usize,
+    #[serde(skip)]
+    equity_curve: Vec<EquityPoint>, // kept for the html charts
+}
+
+impl MetricsReport {
+    pub fn new(trades: &[Trade], equity_curve: &[EquityPoint]) -> MetricsReport {
+        let first = equity_curve
+            .first()
+            .cloned()
+            .unwrap_or(EquityPoint { ts: 0, equity: 0. });
+        let last = equity_curve.last().cloned().unwrap_or(first.clone());
+        MetricsReport {
+            start_ts: first.ts,
+            end_ts: last.ts,
+            initial_equity: first.equity,
+            final_equity: last.equity,
+            net_profit: metrics::net_profit(equity_curve),
+            total_return: metrics::total_return(equity_curve),
+            annualized_return: metrics::annualized_return(equity_curve),
+            max_drawdown: metrics::max_drawdown(equity_curve),
+            max_drawdown_duration_ms: metrics::max_drawdown_duration_ms(equity_curve),
+            sharpe_ratio: metrics::sharpe_ratio(equity_curve),
+            sortino_ratio: metrics::sortino_ratio(equity_curve),
+            calmar_ratio: metrics::calmar_ratio(equity_curve),
+            trades: trades.len(),
+            win_rate: metrics::win_rate(trades),
+            profit_factor: metrics::profit_factor(trades),
+            expectancy: metrics::expectancy(trades),
+            average_win: metrics::average_win(trades),
+            average_loss: metrics::average_loss(trades),
+            exposure_time: metrics::exposure_time(trades, equity_curve),
+            longest_losing_streak: metrics::longest_losing_streak(trades),
+            equity_curve: equity_curve.to_vec(),
+        }
+    }
+
+    pub fn from_result(result: &BacktestResult) -> MetricsReport {
+        MetricsReport::new(&result.trades, &result.equity_curve)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn save_json(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Standalone page with the metrics table and the equity and drawdown
+    /// charts as inline svg.
+    pub fn to_html(&self) -> String {
+        let drawdown = metrics::drawdown_curve(&self.equity_curve);
+        let mut rows = String::new();
+        for (name, value) in self.rows() {
+            let _ = writeln!(rows, "<tr><td>{}</td><td>{}</td></tr>", name, value);
+        }
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Backtest report</title>
+<style>
+body {{ font-family: sans-serif; margin: 24px; color: #222; }}
+table {{ border-collapse: collapse; margin-bottom: 24px; }}
+td {{ padding: 4px 16px; border-bottom: 1px solid #ddd; }}
+td:last-child {{ text-align: right; font-family: monospace; }}
+svg {{ background: #fafafa; border: 1px solid #ddd; display: block; margin-bottom: 24px; }}
+</style>
+</head>
+<body>
+<h1>Backtest report</h1>
+<table>
+{}</table>
+<h2>Equity</h2>
+{}
+<h2>Drawdown</h2>
+{}
+</body>
+</html>
+"#,
+            rows,
+            svg_chart(&self.equity_curve, "#1f77b4"),
+            svg_chart(&drawdown, "#d62728")
+        )
+    }
+
+    pub fn save_html(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_html())?;
+        Ok(())
+    }
+
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        let percent = |v: f64| format!("{:.2}%", v * 100.);
+        vec![
+            ("Start", format_ts(self.start_ts)),
+            ("End", format_ts(self.end_ts)),
+            ("Initial equity", format!("{:.2}", self.initial_equity)),
+            ("Final equity", format!("{:.2}", self.final_equity)),
+            ("Net profit", format!("{:.2}", self.net_profit)),
+            ("Total return", percent(self.total_return)),
+            ("Annualized return", percent(self.annualized_return)),
+            ("Max drawdown", percent(self.max_drawdown)),
+            (
+                "Max drawdown duration",
+                format!("{:.1} days", self.max_drawdown_duration_ms as f64 / DAY_MS),
+            ),
+            ("Sharpe ratio", format!("{:.3}", self.sharpe_ratio)),
+            ("Sortino ratio", format!("{:.3}", self.sortino_ratio)),
+            ("Calmar ratio", format!("{:.3}", self.calmar_ratio)),
+            ("Trades", self.trades.to_string()),
+            ("Win rate", percent(self.win_rate)),
+            (
+                "Profit factor",
+                self.profit_factor
+                    .map_or_else(|| "-".to_owned(), |v| format!("{:.3}", v)),
+            ),
+            ("Expectancy", format!("{:.2}", self.expectancy)),
+            ("Average win", format!("{:.2}", self.average_win)),
+            ("Average loss", format!("{:.2}", self.average_loss)),
+            ("Exposure time", percent(self.exposure_time)),
+            (
+                "Longest losing streak",
+                self.longest_losing_streak.to_string(),
+            ),
+        ]
+    }
+}
+
+impl fmt::Display for MetricsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self.rows();
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, value) in rows {
+            writeln!(f, "{:<width$}  {:>20}", name, value, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_ts(ts: i64) -> String {
+    NaiveDateTime::from_timestamp_millis(ts)
+        .map(|datetime| datetime.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Line chart of the points, thinned out to at most `CHART_MAX_POINTS`.
+fn svg_chart(points: &[EquityPoint], color: &str) -> String {
+    let step = points.len().div_ceil(CHART_MAX_POINTS).max(1);
+    let mut sampled = points.iter().step_by(step).collect::<Vec<_>>();
+    if let Some(last) = points.last() {
+        if sampled.last().map(|p| p.ts) != Some(last.ts) {
+            sampled.push(last);
+        }
+    }
+    let (first, last) = match (sampled.first(), sampled.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return String::new(),
+    };
+    let min = sampled.iter().map(|p| p.equity).fold(f64::MAX, f64::min);
+    let max = sampled.iter().map(|p| p.equity).fold(f64::MIN, f64::max);
+    let range = if max > min { max - min } else { 1. };
+    let span = (last.ts - first.ts).max(1) as f64;
+    let margin = 40.;
+    let mut line = String::new();
+    for point in sampled.iter() {
+        let x = margin + (point.ts - first.ts) as f64 / span * (CHART_WIDTH - 2. * margin);
+        let y = margin + (max - point.equity) / range * (CHART_HEIGHT - 2. * margin);
+        let _ = write!(line, "{:.1},{:.1} ", x, y);
+    }
+    format!(
+        r#"<svg width="{w}" height="{h}" viewBox="0 0 {w} {h}" xmlns="http://www.w3.org/2000/svg">
+<text x="4" y="{top}" font-size="11">{max:.4}</text>
+<text x="4" y="{bottom}" font-size="11">{min:.4}</text>
+<text x="{margin}" y="{h_text}" font-size="11">{start}</text>
+<text x="{end_x}" y="{h_text}" font-size="11" text-anchor="end">{end}</text>
+<polyline fill="none" stroke="{color}" stroke-width="1.5" points="{line}"/>
+</svg>"#,
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        top = margin - 4.,
+        bottom = CHART_HEIGHT - margin + 12.,
+        h_text = CHART_HEIGHT - 6.,
+        end_x = CHART_WIDTH - margin,
+        start = format_ts(first.ts),
+        end = format_ts(last.ts),
+        margin = margin,
+        max = max,
+        min = min,
+        color = color,
+        line = line.trim_end(),
+    )
+}
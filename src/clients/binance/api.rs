@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_std::task;
 use chrono::Utc;
 use hmac::Hmac;
@@ -8,18 +8,26 @@ use serde_json::Value;
 use sha2::Sha256;
 use std::collections::HashMap;
 
-use crate::clients::binance::parser::parse_api_kline;
+use crate::clients::binance::parser::{parse_api_funding_rate, parse_api_kline};
 use crate::types::account::Account;
 use crate::types::account::Asset;
 use crate::types::account::Position;
+use crate::types::funding::FundingRate;
 use crate::types::instrument::InstrumentInfo;
+use crate::types::interval::KlineInterval;
 use crate::types::kline::Kline;
 use crate::types::order::Order;
 
 pub const FUTURES_KLINE: &str = "/fapi/v1/klines";
+pub const FUTURES_FUNDING_RATE: &str = "/fapi/v1/fundingRate";
+pub const FUTURES_TICKER_PRICE: &str = "/fapi/v1/ticker/price";
+pub const FUTURES_PREMIUM_INDEX: &str = "/fapi/v1/premiumIndex";
 pub const FUTURES_ACCOUNT: &str = "/fapi/v2/account";
 pub const FUTURES_EXCHANGE_INFO: &str = "/fapi/v1/exchangeInfo";
 pub const FUTURES_ORDER: &str = "/fapi/v1/order";
+pub const FUTURES_USER_TRADES: &str = "/fapi/v1/userTrades";
+pub const FUTURES_OPEN_ORDERS: &str = "/fapi/v1/openOrders";
+pub const FUTURES_ALL_OPEN_ORDERS: &str = "/fapi/v1/allOpenOrders";
 pub const FUTURES_BASE: &str = "https://fapi.binance.com";
 
 lazy_static::lazy_static! {
@@ -29,6 +37,7 @@ lazy_static::lazy_static! {
     };
 }
 
+#[derive(Clone)]
 pub struct BinanceFuturesApiClient {
     client: reqwest::Client,
     api_key: String,
@@ -45,28 +54,28 @@ impl BinanceFuturesApiClient {
     }
 
     pub fn has_keys(&self) -> bool {
-        self.api_key != "".to_owned() && self.secret_key != "".to_owned()
+        !self.api_key.is_empty() && !self.secret_key.is_empty()
     }
 
     pub async fn get_klines(
         &self,
         symbol: &str,
-        interval: &str, // "1d, 1h, 1m"
+        interval: KlineInterval,
         start_time: Option<&str>,
         end_time: Option<&str>,
         limit: Option<&str>,
     ) -> Result<Vec<Kline>> {
         let mut params = HashMap::new();
         params.insert("symbol", symbol);
-        params.insert("interval", interval);
-        if start_time.is_some() {
-            params.insert("startTime", start_time.unwrap());
+        params.insert("interval", interval.as_str());
+        if let Some(start_time) = start_time {
+            params.insert("startTime", start_time);
         }
-        if end_time.is_some() {
-            params.insert("endTime", end_time.unwrap());
+        if let Some(end_time) = end_time {
+            params.insert("endTime", end_time);
         }
-        if limit.is_some() {
-            params.insert("limit", limit.unwrap());
+        if let Some(limit) = limit {
+            params.insert("limit", limit);
         }
 
         let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_KLINE);
@@ -84,6 +93,68 @@ impl BinanceFuturesApiClient {
         Ok(klines)
     }
 
+    /// Last traded price of the symbol.
+    pub async fn get_price(&self, symbol: &str) -> Result<f64> {
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_TICKER_PRICE);
+        let request_url =
+            reqwest::Url::parse_with_params(endpoint.as_str(), &[("symbol", symbol)]).unwrap();
+        let response = self.client.get(request_url).send().await?;
+        let content = response.text().await?;
+        let value: Value = serde_json::from_str(content.as_str())?;
+        let price = value["price"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Unexpected ticker response: {}", value))?
+            .parse::<f64>()?;
+        Ok(price)
+    }
+
+    /// Mark price of the symbol, the price Binance values positions at.
+    pub async fn get_mark_price(&self, symbol: &str) -> Result<f64> {
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_PREMIUM_INDEX);
+        let request_url =
+            reqwest::Url::parse_with_params(endpoint.as_str(), &[("symbol", symbol)]).unwrap();
+        let response = self.client.get(request_url).send().await?;
+        let content = response.text().await?;
+        let value: Value = serde_json::from_str(content.as_str())?;
+        let price = value["markPrice"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Unexpected premium index response: {}", value))?
+            .parse::<f64>()?;
+        Ok(price)
+    }
+
+    /// Funding rate history from `start_time`, oldest first.
+    pub async fn get_funding_rates(
+        &self,
+        symbol: &str,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        limit: Option<usize>,
+    ) -> Result<Vec<FundingRate>> {
+        let mut params = vec![("symbol", symbol.to_owned())];
+        if let Some(start_time) = start_time {
+            params.push(("startTime", start_time.to_string()));
+        }
+        if let Some(end_time) = end_time {
+            params.push(("endTime", end_time.to_string()));
+        }
+        if let Some(limit) = limit {
+            params.push(("limit", limit.to_string()));
+        }
+
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_FUNDING_RATE);
+        let request_url = reqwest::Url::parse_with_params(endpoint.as_str(), &params).unwrap();
+        let response = self.client.get(request_url).send().await?;
+        let content = response.text().await?;
+        let values: Vec<Value> = serde_json::from_str(content.as_str())?;
+        let mut rates = values
+            .into_iter()
+            .map(parse_api_funding_rate)
+            .collect::<Result<Vec<_>>>()?;
+        rates.sort_by_key(|r| r.funding_time);
+        Ok(rates)
+    }
+
     pub fn hash_signature(&self, params: &mut Vec<(String, String)>, secret_key: &str) {
         let mut request_string = "".to_owned();
         for (k, v) in params.iter() {
@@ -169,6 +240,7 @@ impl BinanceFuturesApiClient {
                 let mut tick_size = "".to_owned();
                 let mut lot_size = "".to_owned();
                 let mut min_qty = 0.;
+                let mut min_notional = 0.;
                 let filters = s["filters"].as_array().unwrap();
                 for f in filters {
                     match f["filterType"].as_str().unwrap() {
@@ -179,6 +251,9 @@ impl BinanceFuturesApiClient {
                             lot_size = f["stepSize"].as_str().unwrap().to_owned();
                             min_qty = f["minQty"].as_str().unwrap().parse::<f64>()?;
                         }
+                        "MIN_NOTIONAL" => {
+                            min_notional = f["notional"].as_str().unwrap().parse::<f64>()?;
+                        }
                         _ => {}
                     }
                 }
@@ -189,6 +264,7 @@ impl BinanceFuturesApiClient {
                         tick_size,
                         lot_size,
                         min_qty,
+                        min_notional,
                     },
                 );
             }
@@ -201,28 +277,28 @@ impl BinanceFuturesApiClient {
         order: Order,
         instrument_info: &InstrumentInfo,
     ) -> Result<Value> {
-        let mut params = Vec::new();
-        params.push(("symbol".to_owned(), order.symbol));
-        params.push(("side".to_owned(), order.order_side.to_string()));
-        params.push(("type".to_owned(), order.order_type.to_string()));
-        params.push(("reduceOnly".to_owned(), order.reduce_only.to_string()));
+        let mut params = vec![
+            ("symbol".to_owned(), order.symbol),
+            ("side".to_owned(), order.order_side.to_string()),
+            ("type".to_owned(), order.order_type.to_string()),
+            ("reduceOnly".to_owned(), order.reduce_only.to_string()),
+            // The default ACK response has no fill price or executed quantity
+            ("newOrderRespType".to_owned(), "RESULT".to_owned()),
+        ];
 
         let lot_precision =
             instrument_info.lot_size.len() - 1 - instrument_info.lot_size.find('.').unwrap_or(0);
         let quantity_string = format!("{:.*}", lot_precision, order.size);
         params.push(("quantity".to_owned(), quantity_string));
 
-        if order.time_in_force.is_some() {
-            params.push((
-                "timeInForce".to_owned(),
-                order.time_in_force.unwrap().to_string(),
-            ));
+        if let Some(time_in_force) = order.time_in_force {
+            params.push(("timeInForce".to_owned(), time_in_force.to_string()));
         }
-        if order.price.is_some() {
+        if let Some(price) = order.price {
             let tick_precision = instrument_info.tick_size.len()
                 - 1
                 - instrument_info.tick_size.find('.').unwrap_or(0);
-            let price_string = format!("{:.*}", tick_precision, order.price.unwrap());
+            let price_string = format!("{:.*}", tick_precision, price);
             params.push(("price".to_owned(), price_string));
         }
         info!("Order params: {:?}", params);
@@ -239,4 +315,77 @@ impl BinanceFuturesApiClient {
         let value: Value = serde_json::from_str(content.as_str())?;
         Ok(value)
     }
+
+    /// Status of an order, with `avgPrice` and `executedQty` once filled.
+    pub async fn get_order(&self, symbol: &str, order_id: i64) -> Result<Value> {
+        let mut params = vec![
+            ("symbol".to_owned(), symbol.to_owned()),
+            ("orderId".to_owned(), order_id.to_string()),
+        ];
+        self.hash_signature(&mut params, &self.secret_key);
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_ORDER);
+        let request_url = reqwest::Url::parse_with_params(endpoint.as_str(), &params).unwrap();
+        let response = self
+            .client
+            .get(request_url)
+            .header("X-MBX-APIKEY", &self.api_key)
+            .send()
+            .await?;
+        let content = response.text().await?;
+        let value: Value = serde_json::from_str(content.as_str())?;
+        Ok(value)
+    }
+
+    /// Trades that filled an order, with the `commission` paid on each.
+    pub async fn get_user_trades(&self, symbol: &str, order_id: i64) -> Result<Value> {
+        let mut params = vec![
+            ("symbol".to_owned(), symbol.to_owned()),
+            ("orderId".to_owned(), order_id.to_string()),
+        ];
+        self.hash_signature(&mut params, &self.secret_key);
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_USER_TRADES);
+        let request_url = reqwest::Url::parse_with_params(endpoint.as_str(), &params).unwrap();
+        let response = self
+            .client
+            .get(request_url)
+            .header("X-MBX-APIKEY", &self.api_key)
+            .send()
+            .await?;
+        let content = response.text().await?;
+        let value: Value = serde_json::from_str(content.as_str())?;
+        Ok(value)
+    }
+
+    /// Open orders of every symbol.
+    pub async fn get_open_orders(&self) -> Result<Value> {
+        let mut params = Vec::new();
+        self.hash_signature(&mut params, &self.secret_key);
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_OPEN_ORDERS);
+        let request_url = reqwest::Url::parse_with_params(endpoint.as_str(), &params).unwrap();
+        let response = self
+            .client
+            .get(request_url)
+            .header("X-MBX-APIKEY", &self.api_key)
+            .send()
+            .await?;
+        let content = response.text().await?;
+        let value: Value = serde_json::from_str(content.as_str())?;
+        Ok(value)
+    }
+
+    pub async fn cancel_all_open_orders(&self, symbol: &str) -> Result<Value> {
+        let mut params = vec![("symbol".to_owned(), symbol.to_owned())];
+        self.hash_signature(&mut params, &self.secret_key);
+        let endpoint = format!("{}{}", FUTURES_BASE, FUTURES_ALL_OPEN_ORDERS);
+        let request_url = reqwest::Url::parse_with_params(endpoint.as_str(), &params).unwrap();
+        let response = self
+            .client
+            .delete(request_url)
+            .header("X-MBX-APIKEY", &self.api_key)
+            .send()
+            .await?;
+        let content = response.text().await?;
+        let value: Value = serde_json::from_str(content.as_str())?;
+        Ok(value)
+    }
 }
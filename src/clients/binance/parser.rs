@@ -3,6 +3,7 @@ use anyhow::Context;
 use anyhow::Result;
 use serde_json::Value;
 
+use crate::types::funding::FundingRate;
 use crate::types::kline::Kline;
 
 pub fn parse_api_kline(value: Value) -> Result<Kline> {
@@ -21,7 +22,91 @@ pub fn parse_api_kline(value: Value) -> Result<Kline> {
     kline.high = value[2].as_str().context(key_err(2))?.parse()?;
     kline.low = value[3].as_str().context(key_err(3))?.parse()?;
     kline.close = value[4].as_str().context(key_err(4))?.parse()?;
+    kline.volume = value[5].as_str().context(key_err(5))?.parse()?;
     kline.close_timestamp = value[6].as_i64().context(key_err(6))?;
+    kline.quote_volume = value[7].as_str().context(key_err(7))?.parse()?;
+    kline.trade_count = value[8].as_i64().context(key_err(8))?;
+    kline.taker_buy_base_volume = value[9].as_str().context(key_err(9))?.parse()?;
+    kline.taker_buy_quote_volume = value[10].as_str().context(key_err(10))?.parse()?;
 
     Ok(kline)
 }
+
+pub fn parse_api_funding_rate(value: Value) -> Result<FundingRate> {
+    let key_err = |key: &str| -> anyhow::Error {
+        anyhow!(
+            "Invalid type in key \"{}\", couldn't parse its value from {}",
+            key,
+            value
+        )
+    };
+    Ok(FundingRate {
+        symbol: value["symbol"]
+            .as_str()
+            .context(key_err("symbol"))?
+            .to_owned(),
+        funding_time: value["fundingTime"]
+            .as_i64()
+            .context(key_err("fundingTime"))?,
+        rate: value["fundingRate"]
+            .as_str()
+            .context(key_err("fundingRate"))?
+            .parse()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_api_kline_reads_every_field() {
+        // Example response of GET /fapi/v1/klines from the Binance docs
+        let value = json!([
+            1499040000000_i64,
+            "0.01634790",
+            "0.80000000",
+            "0.01575800",
+            "0.01577100",
+            "148976.11427815",
+            1499644799999_i64,
+            "2434.19055334",
+            308,
+            "1756.87402397",
+            "28.46694368",
+            "17928899.62484339"
+        ]);
+        let kline = parse_api_kline(value).unwrap();
+        assert_eq!(kline.open_timestamp, 1499040000000);
+        assert_eq!(kline.close_timestamp, 1499644799999);
+        assert_eq!(kline.open, 0.0163479);
+        assert_eq!(kline.high, 0.8);
+        assert_eq!(kline.low, 0.015758);
+        assert_eq!(kline.close, 0.015771);
+        assert_eq!(kline.volume, 148976.11427815);
+        assert_eq!(kline.quote_volume, 2434.19055334);
+        assert_eq!(kline.trade_count, 308);
+        assert_eq!(kline.taker_buy_base_volume, 1756.87402397);
+        assert_eq!(kline.taker_buy_quote_volume, 28.46694368);
+    }
+
+    #[test]
+    fn parse_api_kline_rejects_unquoted_prices() {
+        let value = json!([
+            1499040000000_i64,
+            0.0163479,
+            "0.80000000",
+            "0.01575800",
+            "0.01577100",
+            "148976.11427815",
+            1499644799999_i64,
+            "2434.19055334",
+            308,
+            "1756.87402397",
+            "28.46694368"
+        ]);
+        let err = parse_api_kline(value).unwrap_err();
+        assert!(format!("{:#}", err).contains("index \"1\""));
+    }
+}
@@ -1,31 +1,126 @@
 use chrono::Utc;
+use std::fmt;
 
-use futures::TryStreamExt;
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt, TryStreamExt};
 use mongodb::{
-    bson::{doc, Bson, Document},
-    options::{ClientOptions, FindOptions},
-    Client,
+    bson::{self, doc, Bson, Decimal128, Document},
+    options::{ClientOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions},
+    Client, IndexModel,
 };
 
+use crate::types::account::AccountSnapshot;
+use crate::types::funding::FundingRate;
 use crate::types::kline::Kline;
+use crate::types::order::OrderRecord;
+use crate::types::trade::Trade;
 
+#[derive(Debug)]
+pub enum StorageError {
+    Mongo(mongodb::error::Error),
+    MissingField {
+        id: String,
+        field: String,
+    },
+    InvalidField {
+        id: String,
+        field: String,
+        value: String,
+    },
+    Write(String),
+    Serialization(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Mongo(e) => write!(f, "MongoDB error: {}", e),
+            StorageError::MissingField { id, field } => {
+                write!(f, "Document {} is missing field \"{}\"", id, field)
+            }
+            StorageError::InvalidField { id, field, value } => write!(
+                f,
+                "Document {} has invalid field \"{}\": {}",
+                id, field, value
+            ),
+            StorageError::Write(e) => write!(f, "Write failed: {}", e),
+            StorageError::Serialization(e) => write!(f, "Bson serialization failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Mongo(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for StorageError {
+    fn from(e: mongodb::error::Error) -> Self {
+        StorageError::Mongo(e)
+    }
+}
+
+impl From<bson::ser::Error> for StorageError {
+    fn from(e: bson::ser::Error) -> Self {
+        StorageError::Serialization(e.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug, Clone)]
+pub struct KlineStreamOptions {
+    pub batch_size: u32,
+    pub projection: Document, // must keep the fields `parse_kline` reads
+}
+
+impl Default for KlineStreamOptions {
+    fn default() -> Self {
+        KlineStreamOptions {
+            batch_size: 10000,
+            projection: doc! {
+                "_id": 1,
+                "open_time": 1,
+                "close_time": 1,
+                "open": 1,
+                "high": 1,
+                "low": 1,
+                "close": 1,
+                "volume": 1,
+                "quote_volume": 1,
+                "trade_count": 1,
+                "taker_buy_base_volume": 1,
+                "taker_buy_quote_volume": 1,
+            },
+        }
+    }
+}
+
+const UPSERT_BATCH_SIZE: usize = 1000;
+
+#[derive(Clone)]
 pub struct MongoClient {
     pub client: Client,
 }
 
 impl MongoClient {
-    pub async fn new(connection_string: &str) -> MongoClient {
-        let client_options = ClientOptions::parse(connection_string).await.unwrap();
-        let client = Client::with_options(client_options).unwrap();
-        MongoClient { client }
+    pub async fn new(connection_string: &str) -> StorageResult<MongoClient> {
+        let client_options = ClientOptions::parse(connection_string).await?;
+        let client = Client::with_options(client_options)?;
+        Ok(MongoClient { client })
     }
+
     pub async fn get_klines(
         &self,
         database_name: &str,
         collection_name: &str,
         from_ts: i64,
         to_ts: Option<i64>,
-    ) -> Vec<Kline> {
+    ) -> StorageResult<Vec<Kline>> {
         let mut klines = Vec::new();
         let database = self.client.database(database_name);
         let collection = database.collection::<Document>(collection_name);
@@ -38,23 +133,871 @@ impl MongoClient {
         let find_options = FindOptions::builder()
             .sort(doc! { "close_time": 1 })
             .build();
-        let mut cursor = collection.find(filter, find_options).await.unwrap();
+        let mut cursor = collection.find(filter, find_options).await?;
         // Iterate over the results of the cursor.
-        while let Some(doc) = cursor.try_next().await.unwrap() {
-            let kline = Kline {
-                open_timestamp: doc.get("open_time").unwrap().as_i64().unwrap(),
-                close_timestamp: doc.get("close_time").unwrap().as_i64().unwrap(),
-                open: parse_f64(doc.get("open")),
-                high: parse_f64(doc.get("high")),
-                low: parse_f64(doc.get("low")),
-                close: parse_f64(doc.get("close")),
+        while let Some(doc) = cursor.try_next().await? {
+            klines.push(parse_kline(&doc)?);
+        }
+        Ok(klines)
+    }
+
+    /// Like `get_klines` but decodes the klines lazily while the cursor pulls
+    /// them in batches, so the range never has to fit in memory.
+    pub async fn stream_klines(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        from_ts: i64,
+        to_ts: Option<i64>,
+        options: KlineStreamOptions,
+    ) -> StorageResult<impl Stream<Item = StorageResult<Kline>>> {
+        let database = self.client.database(database_name);
+        let collection = database.collection::<Document>(collection_name);
+        let to_ts = to_ts.unwrap_or_else(|| Utc::now().timestamp_millis());
+        let filter = doc! { "close_time": {"$gte": from_ts, "$lte": to_ts} };
+        let find_options = FindOptions::builder()
+            .sort(doc! { "close_time": 1 })
+            .batch_size(options.batch_size)
+            .projection(options.projection)
+            .build();
+        let cursor = collection.find(filter, find_options).await?;
+        Ok(cursor.map(|doc| parse_kline(&doc?)))
+    }
+
+    /// Stream the klines of several symbols ordered by close time, e.g. for
+    /// portfolio backtests. `collections` holds (symbol, collection) pairs.
+    pub async fn stream_klines_merged(
+        &self,
+        database_name: &str,
+        collections: &[(String, String)],
+        from_ts: i64,
+        to_ts: Option<i64>,
+        options: KlineStreamOptions,
+    ) -> StorageResult<impl Stream<Item = StorageResult<(String, Kline)>>> {
+        let mut streams = Vec::new();
+        for (symbol, collection_name) in collections.iter() {
+            let stream = self
+                .stream_klines(
+                    database_name,
+                    collection_name,
+                    from_ts,
+                    to_ts,
+                    options.clone(),
+                )
+                .await?;
+            streams.push((symbol.clone(), stream.boxed()));
+        }
+        Ok(merge_kline_streams(streams))
+    }
+
+    /// Close time of the newest kline in the collection, none if it's empty.
+    pub async fn get_latest_close_time(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+    ) -> StorageResult<Option<i64>> {
+        let database = self.client.database(database_name);
+        let collection = database.collection::<Document>(collection_name);
+        let find_options = FindOneOptions::builder()
+            .sort(doc! { "close_time": -1 })
+            .build();
+        match collection.find_one(None, find_options).await? {
+            Some(doc) => Ok(Some(parse_i64(&doc, "close_time")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Unique open and close time indexes, built once per collection before
+    /// upserting. Fails on collections holding duplicates, see
+    /// `delete_duplicate_klines`.
+    pub async fn create_kline_indexes(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+    ) -> StorageResult<()> {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<Document>(collection_name);
+        let unique = IndexOptions::builder().unique(true).build();
+        let indexes = ["open_time", "close_time"].map(|key| {
+            IndexModel::builder()
+                .keys(doc! { key: 1 })
+                .options(unique.clone())
+                .build()
+        });
+        collection.create_indexes(indexes, None).await?;
+        Ok(())
+    }
+
+    /// Keep one document per open time. Returns the number deleted.
+    pub async fn delete_duplicate_klines(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+    ) -> StorageResult<u64> {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<Document>(collection_name);
+        let pipeline = [
+            doc! { "$group": {
+                "_id": "$open_time",
+                "ids": { "$push": "$_id" },
+                "count": { "$sum": 1 },
+            } },
+            doc! { "$match": { "count": { "$gt": 1 } } },
+        ];
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        let mut deleted = 0;
+        while let Some(group) = cursor.try_next().await? {
+            let ids = group
+                .get_array("ids")
+                .map_err(|_| StorageError::MissingField {
+                    id: document_id(&group),
+                    field: "ids".to_owned(),
+                })?;
+            let extra = ids.iter().skip(1).cloned().collect::<Vec<_>>();
+            let result = collection
+                .delete_many(doc! { "_id": { "$in": extra } }, None)
+                .await?;
+            deleted += result.deleted_count;
+        }
+        Ok(deleted)
+    }
+
+    /// Write klines keyed on their open time, so syncing the same range twice
+    /// leaves a single document per kline. Returns the number of new klines.
+    pub async fn upsert_klines(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        klines: &[Kline],
+    ) -> StorageResult<u64> {
+        let database = self.client.database(database_name);
+        let mut upserted = 0;
+        for batch in klines.chunks(UPSERT_BATCH_SIZE) {
+            let updates = batch
+                .iter()
+                .map(|kline| {
+                    doc! {
+                        "q": { "open_time": kline.open_timestamp },
+                        "u": { "$set": kline_to_document(kline) },
+                        "upsert": true,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let command = doc! {
+                "update": collection_name,
+                "updates": updates,
+                "ordered": false,
+            };
+            let result = database.run_command(command, None).await?;
+            if let Ok(errors) = result.get_array("writeErrors") {
+                return Err(StorageError::Write(format!("{:?}", errors)));
+            }
+            upserted += result.get_array("upserted").map_or(0, |u| u.len() as u64);
+        }
+        Ok(upserted)
+    }
+
+    /// Write funding rates keyed on their funding time. Returns the number of
+    /// new rates.
+    pub async fn upsert_funding_rates(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        rates: &[FundingRate],
+    ) -> StorageResult<u64> {
+        let database = self.client.database(database_name);
+        let collection = database.collection::<Document>(collection_name);
+        let index = IndexModel::builder()
+            .keys(doc! { "funding_time": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        collection.create_index(index, None).await?;
+
+        let mut upserted = 0;
+        for batch in rates.chunks(UPSERT_BATCH_SIZE) {
+            let mut updates = Vec::with_capacity(batch.len());
+            for rate in batch.iter() {
+                updates.push(doc! {
+                    "q": { "funding_time": rate.funding_time },
+                    "u": { "$set": bson::to_document(rate)? },
+                    "upsert": true,
+                });
+            }
+            let command = doc! {
+                "update": collection_name,
+                "updates": updates,
+                "ordered": false,
+            };
+            let result = database.run_command(command, None).await?;
+            if let Ok(errors) = result.get_array("writeErrors") {
+                return Err(StorageError::Write(format!("{:?}", errors)));
+            }
+            upserted += result.get_array("upserted").map_or(0, |u| u.len() as u64);
+        }
+        Ok(upserted)
+    }
+
+    pub async fn get_funding_rates(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        from_ts: i64,
+        to_ts: Option<i64>,
+    ) -> StorageResult<Vec<FundingRate>> {
+        let filter = doc! {
+            "funding_time": {"$gte": from_ts, "$lte": to_ts.unwrap_or(i64::MAX)}
+        };
+        self.find_sorted(database_name, collection_name, filter, "funding_time")
+            .await
+    }
+
+    /// Funding time of the newest rate in the collection, none if it's empty.
+    pub async fn get_latest_funding_time(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+    ) -> StorageResult<Option<i64>> {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<FundingRate>(collection_name);
+        let find_options = FindOneOptions::builder()
+            .sort(doc! { "funding_time": -1 })
+            .build();
+        Ok(collection
+            .find_one(None, find_options)
+            .await?
+            .map(|rate| rate.funding_time))
+    }
+
+    /// Indexes for the trade, order and account snapshot collections.
+    pub async fn create_journal_indexes(
+        &self,
+        database_name: &str,
+        trade_collection: &str,
+        order_collection: &str,
+        account_collection: &str,
+    ) -> StorageResult<()> {
+        let database = self.client.database(database_name);
+        // Trades journaled before they had an id keep an empty one
+        let with_id = IndexOptions::builder()
+            .unique(true)
+            .partial_filter_expression(doc! { "id": { "$gt": "" } })
+            .build();
+        database
+            .collection::<Document>(trade_collection)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "id": 1 })
+                    .options(with_id)
+                    .build(),
+                None,
+            )
+            .await?;
+        let indexes = [
+            (trade_collection, doc! { "symbol": 1, "entry_ts": 1 }),
+            (trade_collection, doc! { "exit_ts": 1 }),
+            (order_collection, doc! { "symbol": 1, "ts": 1 }),
+            (account_collection, doc! { "ts": 1 }),
+        ];
+        for (collection_name, keys) in indexes {
+            let index = IndexModel::builder().keys(keys).build();
+            database
+                .collection::<Document>(collection_name)
+                .create_index(index, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn insert_trade(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        trade: &Trade,
+    ) -> StorageResult<()> {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<Trade>(collection_name);
+        collection.insert_one(trade, None).await?;
+        Ok(())
+    }
+
+    /// Replace the trade with the same id or insert it, e.g. once it's
+    /// closed or its position was reduced. Trades without an id replace the
+    /// still open trade with the same symbol and entry time.
+    pub async fn upsert_trade(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        trade: &Trade,
+    ) -> StorageResult<()> {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<Trade>(collection_name);
+        if trade.id.is_empty() {
+            let filter = doc! {
+                "symbol": &trade.symbol,
+                "entry_ts": trade.entry_ts,
+                "exit_ts": 0_i64,
             };
-            klines.push(kline);
+            let result = collection.replace_one(filter, trade, None).await?;
+            if result.matched_count == 0 {
+                return Err(StorageError::Write(format!(
+                    "No open trade of {} entered at {}",
+                    trade.symbol, trade.entry_ts
+                )));
+            }
+            return Ok(());
+        }
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection
+            .replace_one(doc! { "id": &trade.id }, trade, options)
+            .await?;
+        Ok(())
+    }
+
+    /// Trades entered within the time range, optionally of one symbol.
+    pub async fn get_trades(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        symbol: Option<&str>,
+        from_ts: i64,
+        to_ts: Option<i64>,
+    ) -> StorageResult<Vec<Trade>> {
+        let mut filter = doc! {
+            "entry_ts": {"$gte": from_ts, "$lte": to_ts.unwrap_or(i64::MAX)}
+        };
+        if let Some(symbol) = symbol {
+            filter.insert("symbol", symbol);
+        }
+        self.find_sorted(database_name, collection_name, filter, "entry_ts")
+            .await
+    }
+
+    pub async fn get_open_trades(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        symbol: Option<&str>,
+    ) -> StorageResult<Vec<Trade>> {
+        let mut filter = doc! { "exit_ts": 0_i64 };
+        if let Some(symbol) = symbol {
+            filter.insert("symbol", symbol);
+        }
+        self.find_sorted(database_name, collection_name, filter, "entry_ts")
+            .await
+    }
+
+    pub async fn insert_order_record(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        record: &OrderRecord,
+    ) -> StorageResult<()> {
+        let doc = bson::to_document(record)?;
+        self.client
+            .database(database_name)
+            .collection::<Document>(collection_name)
+            .insert_one(doc, None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_order_records(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        symbol: Option<&str>,
+        from_ts: i64,
+        to_ts: Option<i64>,
+    ) -> StorageResult<Vec<OrderRecord>> {
+        let mut filter = doc! {
+            "ts": {"$gte": from_ts, "$lte": to_ts.unwrap_or(i64::MAX)}
+        };
+        if let Some(symbol) = symbol {
+            filter.insert("symbol", symbol);
         }
-        klines
+        self.find_sorted(database_name, collection_name, filter, "ts")
+            .await
     }
+
+    pub async fn insert_account_snapshot(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        snapshot: &AccountSnapshot,
+    ) -> StorageResult<()> {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<AccountSnapshot>(collection_name);
+        collection.insert_one(snapshot, None).await?;
+        Ok(())
+    }
+
+    pub async fn get_account_snapshots(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        from_ts: i64,
+        to_ts: Option<i64>,
+    ) -> StorageResult<Vec<AccountSnapshot>> {
+        let filter = doc! {
+            "ts": {"$gte": from_ts, "$lte": to_ts.unwrap_or(i64::MAX)}
+        };
+        self.find_sorted(database_name, collection_name, filter, "ts")
+            .await
+    }
+
+    async fn find_sorted<T>(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        filter: Document,
+        sort_key: &str,
+    ) -> StorageResult<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned + Unpin + Send + Sync,
+    {
+        let collection = self
+            .client
+            .database(database_name)
+            .collection::<T>(collection_name);
+        let find_options = FindOptions::builder().sort(doc! { sort_key: 1 }).build();
+        let cursor = collection.find(filter, find_options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn insert_documents(
+        &self,
+        database_name: &str,
+        collection_name: &str,
+        docs: Vec<Document>,
+    ) -> StorageResult<()> {
+        let database = self.client.database(database_name);
+        let collection = database.collection::<Document>(collection_name);
+        collection.insert_many(docs, None).await?;
+        Ok(())
+    }
+}
+
+pub fn parse_kline(doc: &Document) -> StorageResult<Kline> {
+    Ok(Kline {
+        open_timestamp: parse_i64(doc, "open_time")?,
+        close_timestamp: parse_i64(doc, "close_time")?,
+        open: parse_f64(doc, "open")?,
+        high: parse_f64(doc, "high")?,
+        low: parse_f64(doc, "low")?,
+        close: parse_f64(doc, "close")?,
+        volume: parse_optional_f64(doc, "volume")?,
+        quote_volume: parse_optional_f64(doc, "quote_volume")?,
+        trade_count: parse_optional_i64(doc, "trade_count")?,
+        taker_buy_base_volume: parse_optional_f64(doc, "taker_buy_base_volume")?,
+        taker_buy_quote_volume: parse_optional_f64(doc, "taker_buy_quote_volume")?,
+    })
 }
 
-pub fn parse_f64(bson: Option<&Bson>) -> f64 {
-    bson.unwrap().as_str().unwrap().parse::<f64>().unwrap()
+struct MergeSource<'a> {
+    symbol: String,
+    stream: BoxStream<'a, StorageResult<Kline>>,
+    head: Option<Kline>,
+    done: bool,
+}
+
+/// Merge streams that are each sorted by close time into one sorted stream,
+/// ties are broken by the order of `streams`. A source that fails yields its
+/// error once and is dropped from the merge.
+pub fn merge_kline_streams<'a>(
+    streams: Vec<(String, BoxStream<'a, StorageResult<Kline>>)>,
+) -> impl Stream<Item = StorageResult<(String, Kline)>> + 'a {
+    let sources = streams
+        .into_iter()
+        .map(|(symbol, stream)| MergeSource {
+            symbol,
+            stream,
+            head: None,
+            done: false,
+        })
+        .collect::<Vec<_>>();
+    stream::unfold(sources, |mut sources| async move {
+        for source in sources.iter_mut() {
+            if source.head.is_none() && !source.done {
+                match source.stream.next().await {
+                    Some(Ok(kline)) => source.head = Some(kline),
+                    Some(Err(e)) => {
+                        // A failed cursor isn't polled again
+                        source.done = true;
+                        return Some((Err(e), sources));
+                    }
+                    None => source.done = true,
+                }
+            }
+        }
+        let next = sources
+            .iter_mut()
+            .filter(|s| s.head.is_some())
+            .min_by_key(|s| s.head.as_ref().unwrap().close_timestamp)?;
+        let kline = next.head.take().unwrap();
+        let symbol = next.symbol.clone();
+        Some((Ok((symbol, kline)), sources))
+    })
+}
+
+/// The schema `parse_kline` reads, prices are stored as strings like the
+/// Binance api returns them.
+pub fn kline_to_document(kline: &Kline) -> Document {
+    doc! {
+        "open_time": kline.open_timestamp,
+        "close_time": kline.close_timestamp,
+        "open": kline.open.to_string(),
+        "high": kline.high.to_string(),
+        "low": kline.low.to_string(),
+        "close": kline.close.to_string(),
+        "volume": kline.volume.to_string(),
+        "quote_volume": kline.quote_volume.to_string(),
+        "trade_count": kline.trade_count,
+        "taker_buy_base_volume": kline.taker_buy_base_volume.to_string(),
+        "taker_buy_quote_volume": kline.taker_buy_quote_volume.to_string(),
+    }
+}
+
+/// Accepts numbers stored as string, double, int or Decimal128.
+pub fn parse_f64(doc: &Document, field: &str) -> StorageResult<f64> {
+    let invalid = |bson: &Bson| StorageError::InvalidField {
+        id: document_id(doc),
+        field: field.to_owned(),
+        value: bson_value(bson),
+    };
+    match doc.get(field) {
+        None | Some(Bson::Null) => Err(StorageError::MissingField {
+            id: document_id(doc),
+            field: field.to_owned(),
+        }),
+        Some(Bson::String(s)) => s
+            .parse::<f64>()
+            .map_err(|_| invalid(&Bson::String(s.clone()))),
+        Some(Bson::Double(v)) => Ok(*v),
+        Some(Bson::Int32(v)) => Ok(*v as f64),
+        Some(Bson::Int64(v)) => Ok(*v as f64),
+        Some(Bson::Decimal128(d)) => {
+            decimal128_to_f64(d).ok_or_else(|| invalid(&Bson::Decimal128(*d)))
+        }
+        Some(bson) => Err(invalid(bson)),
+    }
+}
+
+/// Accepts integers stored as int, whole double or string.
+pub fn parse_i64(doc: &Document, field: &str) -> StorageResult<i64> {
+    let invalid = |bson: &Bson| StorageError::InvalidField {
+        id: document_id(doc),
+        field: field.to_owned(),
+        value: bson_value(bson),
+    };
+    match doc.get(field) {
+        None | Some(Bson::Null) => Err(StorageError::MissingField {
+            id: document_id(doc),
+            field: field.to_owned(),
+        }),
+        Some(Bson::Int64(v)) => Ok(*v),
+        Some(Bson::Int32(v)) => Ok(*v as i64),
+        Some(Bson::Double(v)) if v.fract() == 0. => Ok(*v as i64),
+        Some(Bson::String(s)) => s
+            .parse::<i64>()
+            .map_err(|_| invalid(&Bson::String(s.clone()))),
+        Some(bson) => Err(invalid(bson)),
+    }
+}
+
+/// Like `parse_f64` but a missing field reads as 0, for documents written
+/// before the field existed.
+pub fn parse_optional_f64(doc: &Document, field: &str) -> StorageResult<f64> {
+    match doc.get(field) {
+        None | Some(Bson::Null) => Ok(0.),
+        Some(_) => parse_f64(doc, field),
+    }
+}
+
+pub fn parse_optional_i64(doc: &Document, field: &str) -> StorageResult<i64> {
+    match doc.get(field) {
+        None | Some(Bson::Null) => Ok(0),
+        Some(_) => parse_i64(doc, field),
+    }
+}
+
+fn document_id(doc: &Document) -> String {
+    match doc.get("_id") {
+        Some(id) => id.to_string(),
+        None => "without _id".to_owned(),
+    }
+}
+
+/// Stored value for error messages. Bson displays decimals without their
+/// value, so they are decoded, or shown as the raw bits if that fails.
+fn bson_value(bson: &Bson) -> String {
+    match bson {
+        Bson::Decimal128(decimal) => match decimal128_to_f64(decimal) {
+            Some(value) => format!("Decimal128({})", value),
+            None => format!(
+                "Decimal128(0x{:032x})",
+                u128::from_le_bytes(decimal.bytes())
+            ),
+        },
+        bson => bson.to_string(),
+    }
+}
+
+/// Decode the IEEE 754 BID encoding, bson only keeps the raw bytes.
+fn decimal128_to_f64(decimal: &Decimal128) -> Option<f64> {
+    let bits = u128::from_le_bytes(decimal.bytes());
+    let sign = if bits >> 127 == 1 { "-" } else { "" };
+    let (exponent, coefficient) = if (bits >> 125) & 0b11 == 0b11 {
+        if (bits >> 122) & 0b11111 >= 0b11110 {
+            return None; // Infinity or NaN
+        }
+        // The implied coefficient always exceeds the max, which means zero
+        ((bits >> 111) & 0x3fff, 0)
+    } else {
+        ((bits >> 113) & 0x3fff, bits & ((1 << 113) - 1))
+    };
+    let exponent = exponent as i64 - 6176;
+    format!("{}{}e{}", sign, coefficient, exponent)
+        .parse::<f64>()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(negative: bool, exponent: i64, coefficient: u128) -> Decimal128 {
+        let sign = (negative as u128) << 127;
+        let exponent = ((exponent + 6176) as u128) << 113;
+        Decimal128::from_bytes((sign | exponent | coefficient).to_le_bytes())
+    }
+
+    fn closing_at(close_timestamp: i64) -> StorageResult<Kline> {
+        Ok(Kline {
+            close_timestamp,
+            ..Default::default()
+        })
+    }
+
+    fn merged(
+        streams: Vec<(&str, Vec<StorageResult<Kline>>)>,
+    ) -> Vec<StorageResult<(String, i64)>> {
+        let streams = streams
+            .into_iter()
+            .map(|(symbol, klines)| (symbol.to_owned(), stream::iter(klines).boxed()))
+            .collect();
+        let merged = merge_kline_streams(streams)
+            .map(|item| item.map(|(symbol, kline)| (symbol, kline.close_timestamp)))
+            .collect::<Vec<_>>();
+        async_std::task::block_on(merged)
+    }
+
+    #[test]
+    fn merge_orders_by_close_time() {
+        let items = merged(vec![
+            ("BTCUSDT", vec![closing_at(1), closing_at(4), closing_at(5)]),
+            ("ETHUSDT", vec![closing_at(2), closing_at(4)]),
+            ("BNBUSDT", vec![]),
+            ("XRPUSDT", vec![closing_at(0), closing_at(3), closing_at(6)]),
+        ]);
+        let items = items.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        let expected = [
+            ("XRPUSDT", 0),
+            ("BTCUSDT", 1),
+            ("ETHUSDT", 2),
+            ("XRPUSDT", 3),
+            ("BTCUSDT", 4), // ties follow the order of the streams
+            ("ETHUSDT", 4),
+            ("BTCUSDT", 5),
+            ("XRPUSDT", 6),
+        ]
+        .map(|(symbol, ts)| (symbol.to_owned(), ts));
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn merge_drops_a_failed_source() {
+        let items = merged(vec![
+            (
+                "BTCUSDT",
+                vec![
+                    closing_at(1),
+                    Err(StorageError::Write("cursor failed".to_owned())),
+                    closing_at(3),
+                ],
+            ),
+            ("ETHUSDT", vec![closing_at(2), closing_at(4)]),
+        ]);
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].as_ref().unwrap(), &("BTCUSDT".to_owned(), 1));
+        assert!(matches!(items[1], Err(StorageError::Write(_))));
+        let rest = items[2..]
+            .iter()
+            .map(|item| item.as_ref().unwrap().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(rest, [("ETHUSDT".to_owned(), 2), ("ETHUSDT".to_owned(), 4)]);
+    }
+
+    #[test]
+    fn kline_survives_a_document_round_trip() {
+        let kline = Kline {
+            open_timestamp: 1_672_531_200_000,
+            close_timestamp: 1_672_534_799_999,
+            open: 16541.7,
+            high: 16555.,
+            low: 16520.1,
+            close: 0.1 + 0.2,
+            volume: 1234.567,
+            quote_volume: 20_421_337.123_456,
+            trade_count: 98_765,
+            taker_buy_base_volume: 617.283,
+            taker_buy_quote_volume: 10_210_668.5,
+        };
+        let mut bytes = Vec::new();
+        kline_to_document(&kline).to_writer(&mut bytes).unwrap();
+        let doc = Document::from_reader(bytes.as_slice()).unwrap();
+        let parsed = parse_kline(&doc).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&kline).unwrap()
+        );
+    }
+
+    #[test]
+    fn klines_stored_before_the_volume_fields_read_zero() {
+        let doc = doc! {
+            "open_time": 0_i64,
+            "close_time": 59_999_i64,
+            "open": "1.5",
+            "high": 2.,
+            "low": 1_i32,
+            "close": "1.75",
+        };
+        let kline = parse_kline(&doc).unwrap();
+        assert_eq!(
+            (kline.open, kline.high, kline.low, kline.close),
+            (1.5, 2., 1., 1.75)
+        );
+        assert_eq!((kline.volume, kline.quote_volume), (0., 0.));
+        assert_eq!(kline.trade_count, 0);
+        assert_eq!(kline.taker_buy_quote_volume, 0.);
+    }
+
+    #[test]
+    fn decimal128_to_f64_decodes_bid() {
+        // Canonical encoding of 1 from the bson spec
+        let one =
+            Decimal128::from_bytes(0x3040_0000_0000_0000_0000_0000_0000_0001u128.to_le_bytes());
+        assert_eq!(decimal128_to_f64(&one), Some(1.));
+        assert_eq!(decimal128_to_f64(&decimal(false, -1, 15)), Some(1.5));
+        assert_eq!(
+            decimal128_to_f64(&decimal(true, -8, 12345)),
+            Some(-0.00012345)
+        );
+        assert_eq!(decimal128_to_f64(&decimal(false, 3, 42)), Some(42000.));
+        assert_eq!(decimal128_to_f64(&decimal(false, 0, 0)), Some(0.));
+    }
+
+    #[test]
+    fn decimal128_to_f64_rejects_infinity_and_nan() {
+        let infinity = 0x7800_0000_0000_0000_0000_0000_0000_0000u128;
+        let nan = 0x7c00_0000_0000_0000_0000_0000_0000_0000u128;
+        for bits in [infinity, nan, infinity | 1 << 127] {
+            assert_eq!(
+                decimal128_to_f64(&Decimal128::from_bytes(bits.to_le_bytes())),
+                None
+            );
+        }
+        // Coefficients past the max in the second form read as zero
+        let overflow = 0x6c00_0000_0000_0000_0000_0000_0000_0001u128 | (6176 << 111);
+        assert_eq!(
+            decimal128_to_f64(&Decimal128::from_bytes(overflow.to_le_bytes())),
+            Some(0.)
+        );
+    }
+
+    #[test]
+    fn parse_f64_accepts_numeric_types() {
+        let doc = doc! {
+            "string": "1.25",
+            "double": 1.25,
+            "int32": 2_i32,
+            "int64": 3_i64,
+            "decimal": decimal(false, -2, 125),
+            "bool": true,
+            "text": "abc",
+            "null": Bson::Null,
+        };
+        assert_eq!(parse_f64(&doc, "string").unwrap(), 1.25);
+        assert_eq!(parse_f64(&doc, "double").unwrap(), 1.25);
+        assert_eq!(parse_f64(&doc, "int32").unwrap(), 2.);
+        assert_eq!(parse_f64(&doc, "int64").unwrap(), 3.);
+        assert_eq!(parse_f64(&doc, "decimal").unwrap(), 1.25);
+        assert!(matches!(
+            parse_f64(&doc, "bool"),
+            Err(StorageError::InvalidField { .. })
+        ));
+        assert!(matches!(
+            parse_f64(&doc, "text"),
+            Err(StorageError::InvalidField { .. })
+        ));
+        assert!(matches!(
+            parse_f64(&doc, "null"),
+            Err(StorageError::MissingField { .. })
+        ));
+        assert!(matches!(
+            parse_f64(&doc, "missing"),
+            Err(StorageError::MissingField { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_decimals_show_the_stored_value() {
+        let infinity = 0x7800_0000_0000_0000_0000_0000_0000_0000u128;
+        let doc = doc! {
+            "_id": 1,
+            "infinity": Decimal128::from_bytes(infinity.to_le_bytes()),
+            "decimal": decimal(false, -1, 15),
+        };
+        match parse_f64(&doc, "infinity") {
+            Err(StorageError::InvalidField { value, .. }) => {
+                assert_eq!(value, "Decimal128(0x78000000000000000000000000000000)")
+            }
+            result => panic!("{:?}", result),
+        }
+        match parse_i64(&doc, "decimal") {
+            Err(StorageError::InvalidField { value, .. }) => assert_eq!(value, "Decimal128(1.5)"),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn parse_i64_accepts_whole_numbers() {
+        let doc = doc! {
+            "int64": 1_700_000_000_000_i64,
+            "int32": 7_i32,
+            "double": 8.,
+            "fraction": 8.5,
+            "string": "9",
+        };
+        assert_eq!(parse_i64(&doc, "int64").unwrap(), 1_700_000_000_000);
+        assert_eq!(parse_i64(&doc, "int32").unwrap(), 7);
+        assert_eq!(parse_i64(&doc, "double").unwrap(), 8);
+        assert_eq!(parse_i64(&doc, "string").unwrap(), 9);
+        assert!(matches!(
+            parse_i64(&doc, "fraction"),
+            Err(StorageError::InvalidField { .. })
+        ));
+    }
 }
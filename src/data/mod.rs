@@ -0,0 +1,2 @@
+pub mod quality;
+pub mod resample;
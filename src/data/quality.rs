@@ -0,0 +1,288 @@
+use anyhow::Result;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::clients::binance::api::BinanceFuturesApiClient;
+use crate::clients::mongo_client::MongoClient;
+use crate::jobs::kline_sync::KLINE_PAGE_LIMIT;
+use crate::types::interval::KlineInterval;
+use crate::types::kline::Kline;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KlineIssue {
+    // Klines missing between two timestamps, both inclusive
+    Gap {
+        from_ts: i64,
+        to_ts: i64,
+        missing: i64,
+    },
+    Duplicate {
+        open_ts: i64,
+    },
+    OutOfOrder {
+        index: usize,
+        open_ts: i64,
+    },
+    InvalidOhlc {
+        open_ts: i64,
+        close_ts: i64,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub klines: usize,
+    pub issues: Vec<KlineIssue>,
+}
+
+impl QualityReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn missing_klines(&self) -> i64 {
+        self.issues
+            .iter()
+            .map(|issue| match issue {
+                KlineIssue::Gap { missing, .. } => *missing,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Time ranges to refetch, gaps, klines with invalid prices and the
+    /// duplicated klines, whose kept copy may be the wrong one.
+    pub fn repair_ranges(&self) -> Vec<(i64, i64)> {
+        self.issues
+            .iter()
+            .filter_map(|issue| match issue {
+                KlineIssue::Gap { from_ts, to_ts, .. } => Some((*from_ts, *to_ts)),
+                KlineIssue::InvalidOhlc {
+                    open_ts, close_ts, ..
+                } => Some((*open_ts, *close_ts)),
+                KlineIssue::Duplicate { open_ts } => Some((*open_ts, *open_ts)),
+                KlineIssue::OutOfOrder { .. } => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} klines, {} issues, {} missing klines",
+            self.klines,
+            self.issues.len(),
+            self.missing_klines()
+        )?;
+        for issue in self.issues.iter() {
+            writeln!(f, "  {:?}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scan the klines in the given order. Gaps are measured on the sorted,
+/// deduplicated series with each kline's own duration as the step.
+pub fn check_klines(klines: &[Kline]) -> QualityReport {
+    let mut issues = Vec::new();
+    let mut seen = HashSet::new();
+    let mut prev_open_ts = i64::MIN;
+    for (index, kline) in klines.iter().enumerate() {
+        if !seen.insert(kline.open_timestamp) {
+            issues.push(KlineIssue::Duplicate {
+                open_ts: kline.open_timestamp,
+            });
+        } else if kline.open_timestamp < prev_open_ts {
+            issues.push(KlineIssue::OutOfOrder {
+                index,
+                open_ts: kline.open_timestamp,
+            });
+        }
+        prev_open_ts = prev_open_ts.max(kline.open_timestamp);
+        if let Some(reason) = invalid_ohlc(kline) {
+            issues.push(KlineIssue::InvalidOhlc {
+                open_ts: kline.open_timestamp,
+                close_ts: kline.close_timestamp,
+                reason,
+            });
+        }
+    }
+
+    let mut sorted = klines.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|k| k.open_timestamp);
+    sorted.dedup_by_key(|k| k.open_timestamp);
+    for pair in sorted.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        if next.open_timestamp > prev.close_timestamp + 1 {
+            let step = (prev.close_timestamp - prev.open_timestamp + 1).max(1);
+            issues.push(KlineIssue::Gap {
+                from_ts: prev.close_timestamp + 1,
+                to_ts: next.open_timestamp - 1,
+                missing: (next.open_timestamp - prev.close_timestamp - 1 + step - 1) / step,
+            });
+        }
+    }
+    QualityReport {
+        klines: klines.len(),
+        issues,
+    }
+}
+
+fn invalid_ohlc(kline: &Kline) -> Option<String> {
+    if kline.close_timestamp <= kline.open_timestamp {
+        return Some("close time before open time".to_owned());
+    }
+    if kline.open <= 0. || kline.high <= 0. || kline.low <= 0. || kline.close <= 0. {
+        return Some("non positive price".to_owned());
+    }
+    if kline.high < kline.low {
+        return Some(format!("high {} < low {}", kline.high, kline.low));
+    }
+    if kline.open < kline.low || kline.open > kline.high {
+        return Some(format!("open {} outside range", kline.open));
+    }
+    if kline.close < kline.low || kline.close > kline.high {
+        return Some(format!("close {} outside range", kline.close));
+    }
+    None
+}
+
+pub async fn check_collection(
+    mongo_client: &MongoClient,
+    database_name: &str,
+    collection_name: &str,
+    from_ts: i64,
+    to_ts: Option<i64>,
+) -> Result<QualityReport> {
+    let klines = mongo_client
+        .get_klines(database_name, collection_name, from_ts, to_ts)
+        .await?;
+    let report = check_klines(&klines);
+    info!("{}.{}: {}", database_name, collection_name, report);
+    Ok(report)
+}
+
+/// Delete duplicates, which would fail the unique indexes, then refetch the
+/// repair ranges of the report from Binance and upsert them into the
+/// collection. Returns the number of klines written.
+pub async fn repair_collection(
+    api_client: &BinanceFuturesApiClient,
+    mongo_client: &MongoClient,
+    database_name: &str,
+    collection_name: &str,
+    symbol: &str,
+    interval: KlineInterval,
+    report: &QualityReport,
+) -> Result<usize> {
+    let deleted = mongo_client
+        .delete_duplicate_klines(database_name, collection_name)
+        .await?;
+    if deleted > 0 {
+        info!(
+            "Deleted {} duplicate klines from {}.{}",
+            deleted, database_name, collection_name
+        );
+    }
+    mongo_client
+        .create_kline_indexes(database_name, collection_name)
+        .await?;
+    let limit = KLINE_PAGE_LIMIT.to_string();
+    let mut repaired = 0;
+    for (from_ts, to_ts) in report.repair_ranges() {
+        let mut start_ts = from_ts;
+        while start_ts <= to_ts {
+            let klines = api_client
+                .get_klines(
+                    symbol,
+                    interval,
+                    Some(&start_ts.to_string()),
+                    Some(&to_ts.to_string()),
+                    Some(&limit),
+                )
+                .await?;
+            let last = match klines.last() {
+                Some(last) => last.close_timestamp,
+                None => {
+                    warn!(
+                        "Binance has no {} {} klines from {} to {}",
+                        symbol, interval, start_ts, to_ts
+                    );
+                    break;
+                }
+            };
+            mongo_client
+                .upsert_klines(database_name, collection_name, &klines)
+                .await?;
+            repaired += klines.len();
+            start_ts = last + 1;
+        }
+    }
+    info!(
+        "Repaired {} klines in {}.{}",
+        repaired, database_name, collection_name
+    );
+    Ok(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE_MS: i64 = 60_000;
+
+    fn kline(minute: i64) -> Kline {
+        Kline {
+            open_timestamp: minute * MINUTE_MS,
+            close_timestamp: (minute + 1) * MINUTE_MS - 1,
+            open: 10.,
+            high: 11.,
+            low: 9.,
+            close: 10.5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn finds_gaps_duplicates_and_invalid_prices() {
+        let mut bad = kline(5);
+        bad.high = 8.;
+        let klines = [kline(0), kline(1), kline(1), kline(4), bad, kline(3)];
+        let report = check_klines(&klines);
+        assert_eq!(
+            report.issues,
+            vec![
+                KlineIssue::Duplicate { open_ts: MINUTE_MS },
+                KlineIssue::InvalidOhlc {
+                    open_ts: 5 * MINUTE_MS,
+                    close_ts: 6 * MINUTE_MS - 1,
+                    reason: "high 8 < low 9".to_owned(),
+                },
+                KlineIssue::OutOfOrder {
+                    index: 5,
+                    open_ts: 3 * MINUTE_MS,
+                },
+                KlineIssue::Gap {
+                    from_ts: 2 * MINUTE_MS,
+                    to_ts: 3 * MINUTE_MS - 1,
+                    missing: 1,
+                },
+            ]
+        );
+        assert_eq!(report.missing_klines(), 1);
+    }
+
+    #[test]
+    fn repair_ranges_refetch_duplicates() {
+        let report = check_klines(&[kline(0), kline(0), kline(2)]);
+        assert_eq!(
+            report.repair_ranges(),
+            vec![(0, 0), (MINUTE_MS, 2 * MINUTE_MS - 1)]
+        );
+        assert!(check_klines(&[kline(0), kline(1)]).is_clean());
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::VecDeque;
+
+use crate::types::interval::KlineInterval;
+use crate::types::kline::Kline;
+
+/// Aggregate klines into a higher interval one at a time. A bar is emitted as
+/// soon as the kline closing its bucket arrives.
+pub struct Resampler {
+    interval: KlineInterval,
+    keep_partial: bool, // emit buckets with missing constituents
+    current: Option<Kline>,
+    complete: bool,
+}
+
+impl Resampler {
+    pub fn new(interval: KlineInterval, keep_partial: bool) -> Resampler {
+        Resampler {
+            interval,
+            keep_partial,
+            current: None,
+            complete: false,
+        }
+    }
+
+    /// Klines must arrive in time order. Returns the bars finished by this
+    /// kline, a partial previous bucket may be emitted along with a new one.
+    pub fn update(&mut self, kline: &Kline) -> Result<Vec<Kline>> {
+        let mut bars = Vec::new();
+        let (open_ts, close_ts) = self.interval.bucket(kline.open_timestamp)?;
+        match self.current.as_mut() {
+            Some(bar) if bar.open_timestamp == open_ts => {
+                self.complete &= kline.open_timestamp == bar.close_timestamp + 1;
+                bar.high = bar.high.max(kline.high);
+                bar.low = bar.low.min(kline.low);
+                bar.close = kline.close;
+                bar.close_timestamp = kline.close_timestamp;
+                bar.volume += kline.volume;
+                bar.quote_volume += kline.quote_volume;
+                bar.trade_count += kline.trade_count;
+                bar.taker_buy_base_volume += kline.taker_buy_base_volume;
+                bar.taker_buy_quote_volume += kline.taker_buy_quote_volume;
+            }
+            _ => {
+                bars.extend(self.flush()?);
+                self.complete = kline.open_timestamp == open_ts;
+                self.current = Some(Kline {
+                    open_timestamp: open_ts,
+                    ..kline.clone()
+                });
+            }
+        }
+        if kline.close_timestamp >= close_ts {
+            bars.extend(self.flush()?);
+        }
+        Ok(bars)
+    }
+
+    /// Emit the bar being built, e.g. the trailing bucket once the input ends.
+    pub fn flush(&mut self) -> Result<Option<Kline>> {
+        let mut bar = match self.current.take() {
+            Some(bar) => bar,
+            None => return Ok(None),
+        };
+        let (_, close_ts) = self.interval.bucket(bar.open_timestamp)?;
+        let complete = self.complete && bar.close_timestamp >= close_ts;
+        bar.close_timestamp = close_ts;
+        Ok((complete || self.keep_partial).then_some(bar))
+    }
+}
+
+/// Resample a time ordered series. Partial leading and trailing buckets are
+/// kept only if `keep_partial` is set.
+pub fn resample_klines(
+    klines: &[Kline],
+    interval: KlineInterval,
+    keep_partial: bool,
+) -> Result<Vec<Kline>> {
+    let mut resampler = Resampler::new(interval, keep_partial);
+    let mut bars = Vec::new();
+    for kline in klines.iter() {
+        bars.extend(resampler.update(kline)?);
+    }
+    bars.extend(resampler.flush()?);
+    Ok(bars)
+}
+
+pub fn resample_stream<S, E>(
+    klines: S,
+    interval: KlineInterval,
+    keep_partial: bool,
+) -> impl Stream<Item = Result<Kline, E>>
+where
+    S: Stream<Item = Result<Kline, E>> + Unpin,
+    E: From<anyhow::Error>,
+{
+    let resampler = Resampler::new(interval, keep_partial);
+    let state = (klines, resampler, VecDeque::new(), false);
+    stream::unfold(
+        state,
+        |(mut klines, mut resampler, mut bars, mut done)| async move {
+            loop {
+                if let Some(bar) = bars.pop_front() {
+                    return Some((Ok(bar), (klines, resampler, bars, done)));
+                }
+                if done {
+                    return None;
+                }
+                let result = match klines.next().await {
+                    Some(Ok(kline)) => resampler.update(&kline),
+                    Some(Err(e)) => return Some((Err(e), (klines, resampler, bars, done))),
+                    None => {
+                        done = true;
+                        resampler.flush().map(Vec::from_iter)
+                    }
+                };
+                match result {
+                    Ok(finished) => bars.extend(finished),
+                    Err(e) => return Some((Err(e.into()), (klines, resampler, bars, done))),
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    /// Hourly kline `hour` hours after the epoch, rising by 1 each hour.
+    fn hourly(hour: i64) -> Kline {
+        let open = 100. + hour as f64;
+        Kline {
+            open_timestamp: hour * HOUR_MS,
+            close_timestamp: (hour + 1) * HOUR_MS - 1,
+            open,
+            high: open + 2.,
+            low: open - 1.,
+            close: open + 1.,
+            volume: 10.,
+            trade_count: 5,
+            ..Default::default()
+        }
+    }
+
+    fn hours(hours: impl IntoIterator<Item = i64>) -> Vec<Kline> {
+        hours.into_iter().map(hourly).collect()
+    }
+
+    #[test]
+    fn aggregates_complete_buckets() {
+        let bars = resample_klines(&hours(0..8), KlineInterval::Hour4, false).unwrap();
+        assert_eq!(bars.len(), 2);
+        let bar = &bars[1];
+        assert_eq!(bar.open_timestamp, 4 * HOUR_MS);
+        assert_eq!(bar.close_timestamp, 8 * HOUR_MS - 1);
+        assert_eq!(
+            (bar.open, bar.high, bar.low, bar.close),
+            (104., 109., 103., 108.)
+        );
+        assert_eq!(bar.volume, 40.);
+        assert_eq!(bar.trade_count, 20);
+    }
+
+    #[test]
+    fn emits_a_bar_with_the_kline_closing_its_bucket() {
+        let mut resampler = Resampler::new(KlineInterval::Hour4, false);
+        for hour in 0..3 {
+            assert!(resampler.update(&hourly(hour)).unwrap().is_empty());
+        }
+        let bars = resampler.update(&hourly(3)).unwrap();
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].close, 104.);
+        assert!(resampler.flush().unwrap().is_none());
+    }
+
+    #[test]
+    fn drops_partial_buckets_unless_kept() {
+        // Starts mid bucket, misses hour 6 and ends mid bucket
+        let klines = hours([1, 2, 3, 4, 5, 7, 8, 9, 10, 11, 12]);
+        let bars = resample_klines(&klines, KlineInterval::Hour4, false).unwrap();
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].open_timestamp, 8 * HOUR_MS);
+
+        let bars = resample_klines(&klines, KlineInterval::Hour4, true).unwrap();
+        let opens: Vec<_> = bars
+            .iter()
+            .map(|bar| bar.open_timestamp / HOUR_MS)
+            .collect();
+        assert_eq!(opens, vec![0, 4, 8, 12]);
+        // Partial bars still span their whole bucket
+        assert_eq!(bars[0].open, 101.);
+        assert_eq!(bars[1].volume, 30.);
+        assert_eq!(bars[3].close_timestamp, 16 * HOUR_MS - 1);
+    }
+
+    #[test]
+    fn stream_matches_series() {
+        let klines = hours([1, 2, 3, 4, 5, 7, 8, 9, 10, 11, 12]);
+        let stream = stream::iter(klines.iter().cloned().map(Ok::<_, anyhow::Error>));
+        let bars: Vec<_> = async_std::task::block_on(
+            resample_stream(stream, KlineInterval::Hour4, true).collect::<Vec<_>>(),
+        );
+        let bars: Vec<_> = bars.into_iter().map(Result::unwrap).collect();
+        let expected = resample_klines(&klines, KlineInterval::Hour4, true).unwrap();
+        assert_eq!(
+            serde_json::to_string(&bars).unwrap(),
+            serde_json::to_string(&expected).unwrap()
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::backtest::engine::BacktestConfig;
+use crate::types::config::Secret;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HypertuneConfig {
+    #[serde(skip)]
+    pub backtest: BacktestConfig, // filled from the backtest section of `Config`
+    pub search: SearchMethod,
+    pub objective: Objective,
+    pub parameters: BTreeMap<String, ParamSpace>,
+    pub output_path: String, // csv file with every run
+    pub mongo: Option<HypertuneMongoConfig>,
+    pub workers: Option<usize>, // defaults to the number of cpu cores
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HypertuneMongoConfig {
+    pub connection_string: Secret,
+    pub database: String,
+    pub collection: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMethod {
+    Grid,
+    Random { samples: usize, seed: Option<u64> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    NetProfit,
+    Sharpe,
+    Calmar,
+    Custom, // supplied through `Hypertuner::with_custom_objective`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamSpace {
+    Range { min: f64, max: f64, step: f64 },
+    Choice(Vec<Value>),
+}
+
+impl ParamSpace {
+    /// Every value of the space. Ranges whose bounds and step are whole
+    /// numbers produce integers.
+    pub fn values(&self) -> Vec<Value> {
+        match self {
+            ParamSpace::Range { min, max, step } => {
+                let mut values = Vec::new();
+                let mut index = 0.;
+                loop {
+                    let value = min + step * index;
+                    // Tolerate the float error accumulated by the step
+                    if value > max + step * 1e-9 {
+                        break;
+                    }
+                    values.push(self.number(value));
+                    index += 1.;
+                }
+                values
+            }
+            ParamSpace::Choice(choices) => choices.clone(),
+        }
+    }
+
+    pub fn sample(&self, rng: &mut StdRng) -> Value {
+        match self {
+            ParamSpace::Range { min, max, step } => {
+                let steps = ((max - min) / step + 1e-9).floor() as u64;
+                self.number(min + step * rng.gen_range(0..=steps) as f64)
+            }
+            ParamSpace::Choice(choices) => choices[rng.gen_range(0..choices.len())].clone(),
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        match self {
+            ParamSpace::Range { min, max, step } => {
+                min.fract() == 0. && max.fract() == 0. && step.fract() == 0.
+            }
+            ParamSpace::Choice(_) => false,
+        }
+    }
+
+    fn number(&self, value: f64) -> Value {
+        if self.is_integer() {
+            Value::from(value.round() as i64)
+        } else {
+            Value::from(value)
+        }
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        match self {
+            ParamSpace::Range { min, max, step } => {
+                if *step <= 0. {
+                    return Err(anyhow!("Parameter \"{}\" needs a positive step", name));
+                }
+                if min > max {
+                    return Err(anyhow!("Parameter \"{}\" has min > max", name));
+                }
+            }
+            ParamSpace::Choice(choices) => {
+                if choices.is_empty() {
+                    return Err(anyhow!("Parameter \"{}\" has no choices", name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HypertuneConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.parameters.is_empty() {
+            return Err(anyhow!("Hypertune config has no parameters"));
+        }
+        for (name, space) in self.parameters.iter() {
+            space.validate(name)?;
+        }
+        Ok(())
+    }
+
+    /// Parameter sets to backtest, each one a json object keyed by name.
+    pub fn param_sets(&self) -> Vec<Value> {
+        match &self.search {
+            SearchMethod::Grid => {
+                let mut sets = vec![Map::new()];
+                for (name, space) in self.parameters.iter() {
+                    let values = space.values();
+                    sets = sets
+                        .into_iter()
+                        .flat_map(|set| {
+                            values.iter().map(move |value| {
+                                let mut set = set.clone();
+                                set.insert(name.clone(), value.clone());
+                                set
+                            })
+                        })
+                        .collect();
+                }
+                sets.into_iter().map(Value::Object).collect()
+            }
+            SearchMethod::Random { samples, seed } => {
+                let mut rng = match seed {
+                    Some(seed) => StdRng::seed_from_u64(*seed),
+                    None => StdRng::from_entropy(),
+                };
+                (0..*samples)
+                    .map(|_| {
+                        let set = self
+                            .parameters
+                            .iter()
+                            .map(|(name, space)| (name.clone(), space.sample(&mut rng)))
+                            .collect::<Map<_, _>>();
+                        Value::Object(set)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(search: SearchMethod) -> HypertuneConfig {
+        let parameters = BTreeMap::from([
+            (
+                "fast".to_owned(),
+                ParamSpace::Range {
+                    min: 5.,
+                    max: 20.,
+                    step: 5.,
+                },
+            ),
+            (
+                "size".to_owned(),
+                ParamSpace::Range {
+                    min: 0.1,
+                    max: 0.3,
+                    step: 0.1,
+                },
+            ),
+            (
+                "mode".to_owned(),
+                ParamSpace::Choice(vec![json!("long"), json!("short")]),
+            ),
+        ]);
+        HypertuneConfig {
+            backtest: BacktestConfig::default(),
+            search,
+            objective: Objective::NetProfit,
+            parameters,
+            output_path: "hypertune.csv".to_owned(),
+            mongo: None,
+            workers: None,
+        }
+    }
+
+    #[test]
+    fn ranges_include_max_despite_float_steps() {
+        let space = ParamSpace::Range {
+            min: 0.1,
+            max: 0.3,
+            step: 0.1,
+        };
+        let values = space.values();
+        assert_eq!(values.len(), 3);
+        assert!((values[2].as_f64().unwrap() - 0.3).abs() < 1e-12);
+
+        let space = ParamSpace::Range {
+            min: 0.,
+            max: 1.,
+            step: 0.3,
+        };
+        assert_eq!(space.values().len(), 4);
+    }
+
+    #[test]
+    fn whole_number_ranges_give_integers() {
+        let space = ParamSpace::Range {
+            min: 5.,
+            max: 20.,
+            step: 5.,
+        };
+        assert_eq!(
+            space.values(),
+            vec![json!(5), json!(10), json!(15), json!(20)]
+        );
+    }
+
+    #[test]
+    fn grid_is_the_cartesian_product() {
+        let sets = config(SearchMethod::Grid).param_sets();
+        assert_eq!(sets.len(), 4 * 3 * 2);
+        assert_eq!(sets[0], json!({"fast": 5, "mode": "long", "size": 0.1}));
+        let mut unique = sets.iter().map(Value::to_string).collect::<Vec<_>>();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), sets.len());
+    }
+
+    #[test]
+    fn seeded_samples_are_deterministic_and_in_the_space() {
+        let random = |seed| {
+            config(SearchMethod::Random {
+                samples: 20,
+                seed: Some(seed),
+            })
+            .param_sets()
+        };
+        let sets = random(7);
+        assert_eq!(sets.len(), 20);
+        assert_eq!(sets, random(7));
+        assert_ne!(sets, random(8));
+
+        let grid = config(SearchMethod::Grid).param_sets();
+        assert!(sets.iter().all(|set| grid.contains(set)));
+    }
+
+    #[test]
+    fn validate_rejects_empty_spaces() {
+        let mut config = config(SearchMethod::Grid);
+        assert!(config.validate().is_ok());
+        config.parameters.insert(
+            "bad".to_owned(),
+            ParamSpace::Range {
+                min: 1.,
+                max: 0.,
+                step: 1.,
+            },
+        );
+        assert!(config.validate().is_err());
+        config
+            .parameters
+            .insert("bad".to_owned(), ParamSpace::Choice(Vec::new()));
+        assert!(config.validate().is_err());
+        config.parameters.clear();
+        assert!(config.validate().is_err());
+    }
+}
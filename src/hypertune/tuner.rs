@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::backtest::engine::{BacktestResult, Backtester};
+use crate::backtest::metrics;
+use crate::clients::mongo_client::MongoClient;
+use crate::hypertune::config::{HypertuneConfig, Objective};
+use crate::strategy::base::Strategy;
+use crate::types::funding::FundingRate;
+use crate::types::kline::Kline;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HypertuneRun {
+    pub params: Value,
+    pub objective: f64,
+    pub net_profit: f64,
+    pub sharpe: f64,
+    pub calmar: f64,
+    pub max_drawdown: f64,
+    pub trades: usize,
+}
+
+type CustomObjective = Box<dyn Fn(&BacktestResult) -> f64 + Sync>;
+
+pub struct Hypertuner {
+    config: HypertuneConfig,
+    custom_objective: Option<CustomObjective>,
+    funding_rates: Vec<FundingRate>,
+}
+
+impl Hypertuner {
+    pub fn new(config: HypertuneConfig) -> Hypertuner {
+        Hypertuner {
+            config,
+            custom_objective: None,
+            funding_rates: Vec::new(),
+        }
+    }
+
+    /// Funding rate history passed to every backtest.
+    pub fn with_funding_rates(mut self, funding_rates: Vec<FundingRate>) -> Hypertuner {
+        self.funding_rates = funding_rates;
+        self
+    }
+
+    pub fn with_custom_objective<F>(mut self, objective: F) -> Hypertuner
+    where
+        F: Fn(&BacktestResult) -> f64 + Sync + 'static,
+    {
+        self.custom_objective = Some(Box::new(objective));
+        self
+    }
+
+    /// Backtest every parameter set of the search across the worker threads.
+    /// Runs are returned best first by the configured objective. Parameter
+    /// sets the strategy can't be built from are skipped.
+    pub fn run<S, F>(&self, klines: &[Kline], build_strategy: F) -> Result<Vec<HypertuneRun>>
+    where
+        S: Strategy,
+        F: Fn(&Value) -> Result<S> + Sync,
+    {
+        if self.config.objective == Objective::Custom && self.custom_objective.is_none() {
+            return Err(anyhow!(
+                "Hypertune objective \"custom\" requires Hypertuner::with_custom_objective"
+            ));
+        }
+        let param_sets = self.config.param_sets();
+        let workers = self
+            .config
+            .workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .clamp(1, param_sets.len().max(1));
+        info!(
+            "Hypertune {} parameter sets with {} workers",
+            param_sets.len(),
+            workers
+        );
+
+        let next = AtomicUsize::new(0);
+        let runs = Mutex::new(Vec::with_capacity(param_sets.len()));
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let params = match param_sets.get(index) {
+                        Some(params) => params,
+                        None => break,
+                    };
+                    let mut strategy = match build_strategy(params) {
+                        Ok(strategy) => strategy,
+                        Err(e) => {
+                            warn!("Skip run {}: {} => {}", index, params, e);
+                            continue;
+                        }
+                    };
+                    let result = Backtester::new(self.config.backtest.clone())
+                        .with_funding_rates(self.funding_rates.clone())
+                        .run(klines, &mut strategy);
+                    let run = self.evaluate(params.clone(), &result);
+                    info!("Run {}: {} => {}", index, run.params, run.objective);
+                    runs.lock().unwrap().push(run);
+                });
+            }
+        });
+
+        let mut runs = runs.into_inner().unwrap();
+        runs.sort_by(|a, b| b.objective.total_cmp(&a.objective));
+        Ok(runs)
+    }
+
+    fn evaluate(&self, params: Value, result: &BacktestResult) -> HypertuneRun {
+        let curve = &result.equity_curve;
+        let net_profit = metrics::net_profit(curve);
+        let sharpe = metrics::sharpe_ratio(curve);
+        let calmar = metrics::calmar_ratio(curve);
+        let objective = match self.config.objective {
+            Objective::NetProfit => net_profit,
+            Objective::Sharpe => sharpe,
+            Objective::Calmar => calmar,
+            // Checked by `run` before any backtest
+            Objective::Custom => self.custom_objective.as_ref().map_or(0., |f| f(result)),
+        };
+        HypertuneRun {
+            params,
+            objective,
+            net_profit,
+            sharpe,
+            calmar,
+            max_drawdown: metrics::max_drawdown(curve),
+            trades: result.trades.len(),
+        }
+    }
+
+    /// Write the ranked runs to the csv output and, if configured, MongoDB.
+    /// Nothing is written when every run was skipped.
+    pub async fn save(&self, runs: &[HypertuneRun]) -> Result<()> {
+        if runs.is_empty() {
+            warn!("No hypertune runs to save");
+            return Ok(());
+        }
+        let names = self.config.parameters.keys().collect::<Vec<_>>();
+        let mut writer = csv::Writer::from_path(&self.config.output_path)?;
+        let mut header = vec!["rank".to_owned()];
+        header.extend(names.iter().map(|n| n.to_string()));
+        header.extend(
+            [
+                "objective",
+                "net_profit",
+                "sharpe",
+                "calmar",
+                "max_drawdown",
+                "trades",
+            ]
+            .iter()
+            .map(|h| h.to_string()),
+        );
+        writer.write_record(&header)?;
+        for (rank, run) in runs.iter().enumerate() {
+            let mut record = vec![(rank + 1).to_string()];
+            record.extend(names.iter().map(|n| match &run.params[n.as_str()] {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            }));
+            record.extend([
+                run.objective.to_string(),
+                run.net_profit.to_string(),
+                run.sharpe.to_string(),
+                run.calmar.to_string(),
+                run.max_drawdown.to_string(),
+                run.trades.to_string(),
+            ]);
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        info!("Hypertune results written to {}", self.config.output_path);
+
+        if let Some(mongo) = &self.config.mongo {
+            let mongo_client = MongoClient::new(&mongo.connection_string.resolve()?).await?;
+            let docs = runs
+                .iter()
+                .map(bson::to_document)
+                .collect::<Result<Vec<_>, _>>()?;
+            mongo_client
+                .insert_documents(&mongo.database, &mongo.collection, docs)
+                .await?;
+            info!(
+                "Hypertune results inserted into {}.{}",
+                mongo.database, mongo.collection
+            );
+        }
+        Ok(())
+    }
+}
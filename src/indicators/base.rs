@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::kline::Kline;
+
+/// Indicator updated one closed kline at a time. `update` returns None until
+/// enough klines were seen, `batch` runs the same updates over a series so
+/// both forms give identical values.
+pub trait Indicator {
+    type Output;
+
+    fn update(&mut self, kline: &Kline) -> Option<Self::Output>;
+
+    fn batch(mut self, klines: &[Kline]) -> Vec<Option<Self::Output>>
+    where
+        Self: Sized,
+    {
+        klines.iter().map(|kline| self.update(kline)).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bands {
+    pub upper: f64,
+    pub middle: f64,
+    pub lower: f64,
+}
+
+/// True range, the previous close extends the kline's range.
+pub fn true_range(kline: &Kline, prev_close: Option<f64>) -> f64 {
+    match prev_close {
+        Some(prev_close) => kline.high.max(prev_close) - kline.low.min(prev_close),
+        None => kline.high - kline.low,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const HIGHS: [f64; 12] = [
+        10., 11., 12., 11.5, 12.5, 13., 12., 11., 11.5, 13.5, 14., 13.,
+    ];
+    const LOWS: [f64; 12] = [
+        9., 9.5, 10.5, 10., 11., 12., 10.5, 9.5, 10., 11.5, 12.5, 11.5,
+    ];
+    const CLOSES: [f64; 12] = [
+        9.5, 10.5, 11.5, 10.5, 12., 12.5, 11., 10., 11., 13., 13.5, 12.,
+    ];
+    const VOLUMES: [f64; 12] = [
+        100., 200., 150., 120., 300., 250., 180., 220., 160., 400., 350., 200.,
+    ];
+
+    /// Hourly klines from the epoch shared by the indicator tests.
+    pub(crate) fn sample_klines() -> Vec<Kline> {
+        (0..HIGHS.len())
+            .map(|i| Kline {
+                open_timestamp: i as i64 * 3_600_000,
+                close_timestamp: (i as i64 + 1) * 3_600_000 - 1,
+                open: if i == 0 { CLOSES[0] } else { CLOSES[i - 1] },
+                high: HIGHS[i],
+                low: LOWS[i],
+                close: CLOSES[i],
+                volume: VOLUMES[i],
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    pub(crate) fn close_klines(closes: &[f64]) -> Vec<Kline> {
+        closes
+            .iter()
+            .map(|&close| Kline {
+                open: close,
+                high: close,
+                low: close,
+                close,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Asserts the series is None for `warm_up` values and then matches
+    /// `expected` within `tolerance`.
+    pub(crate) fn assert_series(
+        actual: &[Option<f64>],
+        warm_up: usize,
+        expected: &[f64],
+        tolerance: f64,
+    ) {
+        assert_eq!(actual.len(), warm_up + expected.len());
+        assert!(actual[..warm_up].iter().all(Option::is_none), "{actual:?}");
+        for (i, (actual, expected)) in actual[warm_up..].iter().zip(expected).enumerate() {
+            let actual = actual.unwrap_or_else(|| panic!("None at {}", warm_up + i));
+            assert!(
+                (actual - expected).abs() <= tolerance,
+                "{actual} != {expected} at {}",
+                warm_up + i
+            );
+        }
+    }
+
+    #[test]
+    fn true_range_includes_previous_close() {
+        let klines = sample_klines();
+        assert_eq!(true_range(&klines[0], None), 1.);
+        assert_eq!(true_range(&klines[3], Some(klines[2].close)), 1.5);
+        // The previous 13.5 close is above the 11.5 - 13.0 range
+        assert_eq!(true_range(&klines[11], Some(klines[10].close)), 2.);
+    }
+}
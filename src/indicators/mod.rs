@@ -0,0 +1,5 @@
+pub mod base;
+pub mod momentum;
+pub mod moving;
+pub mod volatility;
+pub mod volume;
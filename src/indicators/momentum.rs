@@ -0,0 +1,289 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::indicators::base::{true_range, Indicator};
+use crate::indicators::moving::{Ema, Sma};
+use crate::types::kline::Kline;
+
+/// Wilder's relative strength index of the close, 0 to 100.
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    prev_close: Option<f64>,
+    avg_gain: Ema,
+    avg_loss: Ema,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Rsi {
+        Rsi {
+            prev_close: None,
+            avg_gain: Ema::wilder(period),
+            avg_loss: Ema::wilder(period),
+        }
+    }
+
+    pub fn next(&mut self, value: f64) -> Option<f64> {
+        let prev_close = self.prev_close.replace(value)?;
+        let change = value - prev_close;
+        let avg_gain = self.avg_gain.next(change.max(0.));
+        let avg_loss = self.avg_loss.next((-change).max(0.));
+        let (avg_gain, avg_loss) = (avg_gain?, avg_loss?);
+        if avg_loss == 0. {
+            return Some(if avg_gain == 0. { 50. } else { 100. });
+        }
+        Some(100. - 100. / (1. + avg_gain / avg_loss))
+    }
+}
+
+impl Indicator for Rsi {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.next(kline.close)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MacdOutput {
+    pub macd: f64,
+    pub signal: f64,
+    pub histogram: f64,
+}
+
+/// Fast EMA minus slow EMA of the close, with an EMA of it as the signal.
+#[derive(Debug, Clone)]
+pub struct Macd {
+    fast: Ema,
+    slow: Ema,
+    signal: Ema,
+}
+
+impl Macd {
+    pub fn new(fast_period: usize, slow_period: usize, signal_period: usize) -> Macd {
+        Macd {
+            fast: Ema::new(fast_period),
+            slow: Ema::new(slow_period),
+            signal: Ema::new(signal_period),
+        }
+    }
+
+    pub fn next(&mut self, value: f64) -> Option<MacdOutput> {
+        let fast = self.fast.next(value);
+        let slow = self.slow.next(value);
+        let macd = fast? - slow?;
+        let signal = self.signal.next(macd)?;
+        Some(MacdOutput {
+            macd,
+            signal,
+            histogram: macd - signal,
+        })
+    }
+}
+
+impl Indicator for Macd {
+    type Output = MacdOutput;
+
+    fn update(&mut self, kline: &Kline) -> Option<MacdOutput> {
+        self.next(kline.close)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StochasticOutput {
+    pub k: f64,
+    pub d: f64,
+}
+
+/// %K, where the close sits in the high low range of the last `k_period`
+/// klines, and %D, the SMA of %K over `d_period`.
+#[derive(Debug, Clone)]
+pub struct Stochastic {
+    k_period: usize,
+    window: VecDeque<(f64, f64)>, // high, low
+    d: Sma,
+}
+
+impl Stochastic {
+    pub fn new(k_period: usize, d_period: usize) -> Stochastic {
+        Stochastic {
+            k_period: k_period.max(1),
+            window: VecDeque::new(),
+            d: Sma::new(d_period),
+        }
+    }
+}
+
+impl Indicator for Stochastic {
+    type Output = StochasticOutput;
+
+    fn update(&mut self, kline: &Kline) -> Option<StochasticOutput> {
+        self.window.push_back((kline.high, kline.low));
+        if self.window.len() > self.k_period {
+            self.window.pop_front();
+        }
+        if self.window.len() < self.k_period {
+            return None;
+        }
+        let high = self.window.iter().map(|w| w.0).fold(f64::MIN, f64::max);
+        let low = self.window.iter().map(|w| w.1).fold(f64::MAX, f64::min);
+        // A flat range has no position in it, call it the middle
+        let k = if high > low {
+            100. * (kline.close - low) / (high - low)
+        } else {
+            50.
+        };
+        let d = self.d.next(k)?;
+        Some(StochasticOutput { k, d })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdxOutput {
+    pub adx: f64,
+    pub plus_di: f64,
+    pub minus_di: f64,
+}
+
+/// Wilder's average directional index with the +DI and -DI lines.
+#[derive(Debug, Clone)]
+pub struct Adx {
+    prev: Option<Kline>,
+    tr: Ema,
+    plus_dm: Ema,
+    minus_dm: Ema,
+    adx: Ema,
+}
+
+impl Adx {
+    pub fn new(period: usize) -> Adx {
+        Adx {
+            prev: None,
+            tr: Ema::wilder(period),
+            plus_dm: Ema::wilder(period),
+            minus_dm: Ema::wilder(period),
+            adx: Ema::wilder(period),
+        }
+    }
+}
+
+impl Indicator for Adx {
+    type Output = AdxOutput;
+
+    fn update(&mut self, kline: &Kline) -> Option<AdxOutput> {
+        let prev = self.prev.replace(kline.clone())?;
+        let up = kline.high - prev.high;
+        let down = prev.low - kline.low;
+        let plus_dm = if up > down && up > 0. { up } else { 0. };
+        let minus_dm = if down > up && down > 0. { down } else { 0. };
+        let tr = self.tr.next(true_range(kline, Some(prev.close)));
+        let plus_dm = self.plus_dm.next(plus_dm);
+        let minus_dm = self.minus_dm.next(minus_dm);
+        let (tr, plus_dm, minus_dm) = (tr?, plus_dm?, minus_dm?);
+        let (plus_di, minus_di) = if tr > 0. {
+            (100. * plus_dm / tr, 100. * minus_dm / tr)
+        } else {
+            (0., 0.)
+        };
+        let di_sum = plus_di + minus_di;
+        let dx = if di_sum > 0. {
+            100. * (plus_di - minus_di).abs() / di_sum
+        } else {
+            0.
+        };
+        let adx = self.adx.next(dx)?;
+        Some(AdxOutput {
+            adx,
+            plus_di,
+            minus_di,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indicators::base::tests::{assert_series, close_klines, sample_klines};
+
+    // Wilder's RSI example as published by StockCharts
+    const RSI_CLOSES: [f64; 33] = [
+        44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03, 45.61,
+        46.28, 46.28, 46.00, 46.03, 46.41, 46.22, 45.64, 46.21, 46.25, 45.71, 46.45, 45.78, 45.35,
+        44.03, 44.18, 44.22, 44.57, 43.42, 42.66, 43.13,
+    ];
+
+    #[test]
+    fn rsi_matches_reference() {
+        let values = Rsi::new(14).batch(&close_klines(&RSI_CLOSES));
+        let expected = [
+            70.4641, 66.2496, 66.4809, 69.3469, 66.2947, 57.915, 62.8807, 63.2088, 56.0116,
+            62.3399, 54.671, 50.3868, 40.0194, 41.4926, 41.9024, 45.4995, 37.3228, 33.0905,
+            37.7888,
+        ];
+        assert_series(&values, 14, &expected, 5e-4);
+        // StockCharts rounds its averages, so only agrees to about 0.1
+        let published = [
+            70.53, 66.32, 66.55, 69.41, 66.36, 57.97, 62.93, 63.26, 56.06, 62.38, 54.71, 50.42,
+            39.99, 41.46, 41.87, 45.46, 37.30, 33.08, 37.77,
+        ];
+        assert_series(&values, 14, &published, 0.1);
+    }
+
+    #[test]
+    fn rsi_of_flat_and_rising_closes() {
+        let flat = Rsi::new(3).batch(&close_klines(&[10.; 5]));
+        assert_series(&flat, 3, &[50., 50.], 0.);
+        let rising = Rsi::new(3).batch(&close_klines(&[1., 2., 3., 4., 5.]));
+        assert_series(&rising, 3, &[100., 100.], 0.);
+    }
+
+    #[test]
+    fn macd_matches_reference() {
+        let values = Macd::new(3, 5, 2).batch(&sample_klines());
+        let macd = [
+            0.508333, 0.193056, -0.11088, -0.027045, 0.338741, 0.487546, 0.20589,
+        ];
+        let signal = [
+            0.479167, 0.288426, 0.022222, -0.010622, 0.222287, 0.399126, 0.270302,
+        ];
+        let field = |f: fn(&MacdOutput) -> f64| -> Vec<_> {
+            values.iter().map(|v| v.as_ref().map(f)).collect()
+        };
+        assert_series(&field(|v| v.macd), 5, &macd, 1e-6);
+        assert_series(&field(|v| v.signal), 5, &signal, 1e-6);
+        let histogram: Vec<_> = macd.iter().zip(signal).map(|(m, s)| m - s).collect();
+        assert_series(&field(|v| v.histogram), 5, &histogram, 2e-6);
+    }
+
+    #[test]
+    fn stochastic_matches_reference() {
+        let values = Stochastic::new(5, 3).batch(&sample_klines());
+        let k = [33.333333, 14.285714, 42.857143, 87.5, 88.888889, 55.555556];
+        let d = [
+            68.253968, 44.444444, 30.15873, 48.214286, 73.082011, 77.314815,
+        ];
+        let k_values: Vec<_> = values.iter().map(|v| v.map(|v| v.k)).collect();
+        let d_values: Vec<_> = values.iter().map(|v| v.map(|v| v.d)).collect();
+        assert_series(&k_values, 6, &k, 1e-6);
+        assert_series(&d_values, 6, &d, 1e-6);
+    }
+
+    #[test]
+    fn adx_matches_reference() {
+        let values = Adx::new(3).batch(&sample_klines());
+        let adx = [
+            71.829268, 50.953664, 47.083863, 35.244861, 39.895528, 45.868054, 32.110364,
+        ];
+        let plus_di = [
+            47.435897, 28.030303, 19.19585, 23.734038, 48.784659, 44.364508, 28.219208,
+        ];
+        let minus_di = [
+            5.128205, 33.712121, 44.098573, 29.942756, 16.611701, 11.859603, 25.739801,
+        ];
+        let field = |f: fn(&AdxOutput) -> f64| -> Vec<_> {
+            values.iter().map(|v| v.as_ref().map(f)).collect()
+        };
+        assert_series(&field(|v| v.adx), 5, &adx, 1e-6);
+        assert_series(&field(|v| v.plus_di), 5, &plus_di, 1e-6);
+        assert_series(&field(|v| v.minus_di), 5, &minus_di, 1e-6);
+    }
+}
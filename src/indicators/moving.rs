@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+
+use crate::indicators::base::Indicator;
+use crate::types::kline::Kline;
+
+/// Simple moving average of the close.
+#[derive(Debug, Clone)]
+pub struct Sma {
+    period: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Sma {
+        Sma {
+            period: period.max(1),
+            window: VecDeque::new(),
+            sum: 0.,
+        }
+    }
+
+    pub fn next(&mut self, value: f64) -> Option<f64> {
+        self.window.push_back(value);
+        self.sum += value;
+        if self.window.len() > self.period {
+            self.sum -= self.window.pop_front().unwrap();
+        }
+        (self.window.len() == self.period).then(|| self.sum / self.period as f64)
+    }
+}
+
+impl Indicator for Sma {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.next(kline.close)
+    }
+}
+
+/// Exponential moving average of the close with `2 / (period + 1)` as the
+/// smoothing factor, seeded with the SMA of the first `period` values.
+#[derive(Debug, Clone)]
+pub struct Ema {
+    alpha: f64,
+    seed: Sma,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(period: usize) -> Ema {
+        Ema::with_alpha(period, 2. / (period.max(1) as f64 + 1.))
+    }
+
+    /// Wilder's smoothing uses `1 / period`.
+    pub fn wilder(period: usize) -> Ema {
+        Ema::with_alpha(period, 1. / period.max(1) as f64)
+    }
+
+    fn with_alpha(period: usize, alpha: f64) -> Ema {
+        Ema {
+            alpha,
+            seed: Sma::new(period),
+            value: None,
+        }
+    }
+
+    pub fn next(&mut self, value: f64) -> Option<f64> {
+        self.value = match self.value {
+            Some(prev) => Some(prev + self.alpha * (value - prev)),
+            None => self.seed.next(value),
+        };
+        self.value
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+impl Indicator for Ema {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.next(kline.close)
+    }
+}
+
+/// Linearly weighted moving average of the close, the latest weighs `period`.
+#[derive(Debug, Clone)]
+pub struct Wma {
+    period: usize,
+    window: VecDeque<f64>,
+}
+
+impl Wma {
+    pub fn new(period: usize) -> Wma {
+        Wma {
+            period: period.max(1),
+            window: VecDeque::new(),
+        }
+    }
+
+    pub fn next(&mut self, value: f64) -> Option<f64> {
+        self.window.push_back(value);
+        if self.window.len() > self.period {
+            self.window.pop_front();
+        }
+        if self.window.len() < self.period {
+            return None;
+        }
+        let weighted: f64 = self
+            .window
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i + 1) as f64 * v)
+            .sum();
+        let weights = (self.period * (self.period + 1) / 2) as f64;
+        Some(weighted / weights)
+    }
+}
+
+impl Indicator for Wma {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.next(kline.close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indicators::base::tests::{assert_series, close_klines, sample_klines};
+
+    // 10 day moving average example as published by StockCharts
+    const MA_CLOSES: [f64; 30] = [
+        22.27, 22.19, 22.08, 22.17, 22.18, 22.13, 22.23, 22.43, 22.24, 22.29, 22.15, 22.39, 22.38,
+        22.61, 23.36, 24.05, 23.75, 23.83, 23.95, 23.63, 23.82, 23.87, 23.65, 23.19, 23.10, 23.33,
+        22.68, 23.10, 22.40, 22.17,
+    ];
+
+    #[test]
+    fn sma_matches_reference() {
+        let values = Sma::new(3).batch(&sample_klines());
+        let expected = [
+            10.5, 10.833333, 11.333333, 11.666667, 11.833333, 11.166667, 10.666667, 11.333333,
+            12.5, 12.833333,
+        ];
+        assert_series(&values, 2, &expected, 1e-6);
+    }
+
+    #[test]
+    fn ema_seeds_with_sma() {
+        let values = Ema::new(3).batch(&sample_klines());
+        let expected = [
+            10.5, 10.5, 11.25, 11.875, 11.4375, 10.71875, 10.859375, 11.929688, 12.714844,
+            12.357422,
+        ];
+        assert_series(&values, 2, &expected, 1e-6);
+    }
+
+    #[test]
+    fn wma_matches_reference() {
+        let values = Wma::new(3).batch(&sample_klines());
+        let expected = [
+            10.833333, 10.833333, 11.416667, 12., 11.666667, 10.75, 10.666667, 11.833333,
+            12.916667, 12.666667,
+        ];
+        assert_series(&values, 2, &expected, 1e-6);
+    }
+
+    #[test]
+    fn sma_matches_published_example() {
+        let values = Sma::new(10).batch(&close_klines(&MA_CLOSES));
+        let expected = [
+            22.221, 22.209, 22.229, 22.259, 22.303, 22.421, 22.613, 22.765, 22.905, 23.076, 23.21,
+            23.377, 23.525, 23.652, 23.71, 23.684, 23.612, 23.505, 23.432, 23.277, 23.131,
+        ];
+        assert_series(&values, 9, &expected, 1e-9);
+        // StockCharts rounds to cents
+        let published = [
+            22.22, 22.21, 22.23, 22.26, 22.30, 22.42, 22.61, 22.77, 22.91, 23.08, 23.21, 23.38,
+            23.53, 23.65, 23.71, 23.68, 23.61, 23.51, 23.43, 23.28, 23.13,
+        ];
+        assert_series(&values, 9, &published, 0.005 + 1e-9);
+    }
+
+    #[test]
+    fn ema_matches_published_example() {
+        let values = Ema::new(10).batch(&close_klines(&MA_CLOSES));
+        let expected = [
+            22.221, 22.208091, 22.241165, 22.266408, 22.328879, 22.516356, 22.7952, 22.9688,
+            23.125382, 23.275312, 23.339801, 23.42711, 23.507635, 23.53352, 23.471062, 23.403596,
+            23.390215, 23.261085, 23.231797, 23.080561, 22.915004,
+        ];
+        assert_series(&values, 9, &expected, 1e-6);
+        let published = [
+            22.22, 22.21, 22.24, 22.27, 22.33, 22.52, 22.80, 22.97, 23.13, 23.28, 23.34, 23.43,
+            23.51, 23.53, 23.47, 23.40, 23.39, 23.26, 23.23, 23.08, 22.92,
+        ];
+        assert_series(&values, 9, &published, 0.005 + 1e-9);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+
+use crate::indicators::base::{true_range, Bands, Indicator};
+use crate::indicators::moving::{Ema, Sma};
+use crate::types::kline::Kline;
+
+/// Wilder's average true range.
+#[derive(Debug, Clone)]
+pub struct Atr {
+    prev_close: Option<f64>,
+    atr: Ema,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Atr {
+        Atr {
+            prev_close: None,
+            atr: Ema::wilder(period),
+        }
+    }
+}
+
+impl Indicator for Atr {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        let tr = true_range(kline, self.prev_close);
+        self.prev_close = Some(kline.close);
+        self.atr.next(tr)
+    }
+}
+
+/// SMA of the close plus and minus `k` population standard deviations.
+#[derive(Debug, Clone)]
+pub struct BollingerBands {
+    period: usize,
+    k: f64,
+    window: VecDeque<f64>,
+    sma: Sma,
+}
+
+impl BollingerBands {
+    pub fn new(period: usize, k: f64) -> BollingerBands {
+        BollingerBands {
+            period: period.max(1),
+            k,
+            window: VecDeque::new(),
+            sma: Sma::new(period),
+        }
+    }
+}
+
+impl Indicator for BollingerBands {
+    type Output = Bands;
+
+    fn update(&mut self, kline: &Kline) -> Option<Bands> {
+        self.window.push_back(kline.close);
+        if self.window.len() > self.period {
+            self.window.pop_front();
+        }
+        let middle = self.sma.next(kline.close)?;
+        let variance = self
+            .window
+            .iter()
+            .map(|v| (v - middle).powi(2))
+            .sum::<f64>()
+            / self.period as f64;
+        let width = self.k * variance.sqrt();
+        Some(Bands {
+            upper: middle + width,
+            middle,
+            lower: middle - width,
+        })
+    }
+}
+
+/// Highest high and lowest low of the last `period` klines, the middle is
+/// halfway between.
+#[derive(Debug, Clone)]
+pub struct Donchian {
+    period: usize,
+    window: VecDeque<(f64, f64)>, // high, low
+}
+
+impl Donchian {
+    pub fn new(period: usize) -> Donchian {
+        Donchian {
+            period: period.max(1),
+            window: VecDeque::new(),
+        }
+    }
+}
+
+impl Indicator for Donchian {
+    type Output = Bands;
+
+    fn update(&mut self, kline: &Kline) -> Option<Bands> {
+        self.window.push_back((kline.high, kline.low));
+        if self.window.len() > self.period {
+            self.window.pop_front();
+        }
+        if self.window.len() < self.period {
+            return None;
+        }
+        let upper = self.window.iter().map(|w| w.0).fold(f64::MIN, f64::max);
+        let lower = self.window.iter().map(|w| w.1).fold(f64::MAX, f64::min);
+        Some(Bands {
+            upper,
+            middle: (upper + lower) / 2.,
+            lower,
+        })
+    }
+}
+
+/// EMA of the close plus and minus `multiplier` ATRs.
+#[derive(Debug, Clone)]
+pub struct Keltner {
+    ema: Ema,
+    atr: Atr,
+    multiplier: f64,
+}
+
+impl Keltner {
+    pub fn new(ema_period: usize, atr_period: usize, multiplier: f64) -> Keltner {
+        Keltner {
+            ema: Ema::new(ema_period),
+            atr: Atr::new(atr_period),
+            multiplier,
+        }
+    }
+}
+
+impl Indicator for Keltner {
+    type Output = Bands;
+
+    fn update(&mut self, kline: &Kline) -> Option<Bands> {
+        let middle = self.ema.next(kline.close);
+        let atr = self.atr.update(kline);
+        let (middle, atr) = (middle?, atr?);
+        Some(Bands {
+            upper: middle + self.multiplier * atr,
+            middle,
+            lower: middle - self.multiplier * atr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indicators::base::tests::{assert_series, close_klines, sample_klines};
+
+    // 14 day ATR example as published by StockCharts, high, low and close
+    const ATR_KLINES: [(f64, f64, f64); 21] = [
+        (48.70, 47.79, 48.16),
+        (48.72, 48.14, 48.61),
+        (48.90, 48.39, 48.75),
+        (48.87, 48.37, 48.63),
+        (48.82, 48.24, 48.74),
+        (49.05, 48.64, 49.03),
+        (49.20, 48.94, 49.07),
+        (49.35, 48.86, 49.32),
+        (49.92, 49.50, 49.91),
+        (50.19, 49.87, 50.13),
+        (50.12, 49.20, 49.53),
+        (49.66, 48.90, 49.50),
+        (49.88, 49.43, 49.75),
+        (50.19, 49.73, 50.03),
+        (50.36, 49.26, 50.31),
+        (50.57, 50.09, 50.52),
+        (50.65, 50.30, 50.41),
+        (50.43, 49.21, 49.34),
+        (49.63, 48.98, 49.37),
+        (50.33, 49.61, 50.23),
+        (50.29, 49.20, 49.24),
+    ];
+
+    // 20 day Bollinger Bands example as published by StockCharts
+    const BOLLINGER_CLOSES: [f64; 42] = [
+        86.16, 89.09, 88.78, 90.32, 89.07, 91.15, 89.44, 89.18, 86.93, 87.68, 86.96, 89.43, 89.32,
+        88.72, 87.45, 87.26, 89.50, 87.90, 89.13, 90.70, 92.90, 92.98, 91.80, 92.66, 92.68, 92.30,
+        92.77, 92.54, 92.95, 93.20, 91.07, 89.83, 89.74, 90.40, 90.74, 88.02, 88.09, 88.84, 90.78,
+        90.54, 91.39, 90.65,
+    ];
+
+    fn assert_bands(values: &[Option<Bands>], warm_up: usize, expected: &[(f64, f64, f64)]) {
+        let field =
+            |f: fn(&Bands) -> f64| -> Vec<_> { values.iter().map(|v| v.as_ref().map(f)).collect() };
+        let upper: Vec<_> = expected.iter().map(|e| e.0).collect();
+        let middle: Vec<_> = expected.iter().map(|e| e.1).collect();
+        let lower: Vec<_> = expected.iter().map(|e| e.2).collect();
+        assert_series(&field(|b| b.upper), warm_up, &upper, 1e-5);
+        assert_series(&field(|b| b.middle), warm_up, &middle, 1e-5);
+        assert_series(&field(|b| b.lower), warm_up, &lower, 1e-5);
+    }
+
+    #[test]
+    fn atr_matches_reference() {
+        let values = Atr::new(3).batch(&sample_klines());
+        let expected = [
+            1.333333, 1.388889, 1.592593, 1.395062, 1.596708, 1.564472, 1.542981, 1.861988,
+            1.741325, 1.82755,
+        ];
+        assert_series(&values, 2, &expected, 1e-6);
+    }
+
+    #[test]
+    fn atr_matches_published_example() {
+        let klines: Vec<_> = ATR_KLINES
+            .iter()
+            .map(|&(high, low, close)| Kline {
+                high,
+                low,
+                close,
+                ..Default::default()
+            })
+            .collect();
+        let values = Atr::new(14).batch(&klines);
+        let expected = [
+            0.554286, 0.593265, 0.585175, 0.568377, 0.614921, 0.617427, 0.641896, 0.673904,
+        ];
+        assert_series(&values, 13, &expected, 1e-6);
+        // StockCharts rounds its values, so only agrees to about 0.01
+        let published = [0.55, 0.59, 0.59, 0.57, 0.62, 0.62, 0.64, 0.67];
+        assert_series(&values, 13, &published, 0.01);
+    }
+
+    #[test]
+    fn bollinger_matches_published_example() {
+        let values = BollingerBands::new(20, 2.).batch(&close_klines(&BOLLINGER_CLOSES));
+        let expected = [
+            (91.291911, 88.7085, 86.125089),
+            (91.949721, 89.0455, 86.141279),
+            (92.613254, 89.24, 85.866746),
+            (92.93445, 89.391, 85.84755),
+            (93.311412, 89.508, 85.704588),
+            (93.727011, 89.6885, 85.649989),
+            (93.897281, 89.746, 85.594719),
+            (94.263642, 89.9125, 85.561358),
+            (94.563019, 90.0805, 85.597981),
+            (94.785163, 90.3815, 85.977837),
+            (95.041187, 90.6575, 86.273813),
+            (94.906207, 90.863, 86.819793),
+            (94.901538, 90.883, 86.864462),
+            (94.893934, 90.904, 86.914066),
+            (94.859458, 90.988, 87.116542),
+            (94.672266, 91.1525, 87.632734),
+            (94.554304, 91.1905, 87.826696),
+            (94.676172, 91.12, 87.563828),
+            (94.573395, 91.167, 87.760605),
+            (94.53224, 91.2495, 87.96676),
+            (94.530331, 91.2415, 87.952669),
+            (94.367234, 91.166, 87.964766),
+            (94.146069, 91.0495, 87.952931),
+        ];
+        assert_bands(&values, 19, &expected);
+        // First published row, upper, middle and lower rounded to cents
+        let first = values[19].unwrap();
+        assert!((first.upper - 91.29).abs() < 0.005);
+        assert!((first.middle - 88.71).abs() < 0.005);
+        assert!((first.lower - 86.13).abs() < 0.005);
+    }
+
+    #[test]
+    fn bollinger_matches_reference() {
+        let values = BollingerBands::new(5, 2.).batch(&sample_klines());
+        let expected = [
+            (12.54356, 10.8, 9.05644),
+            (13.0, 11.4, 9.8),
+            (12.914214, 11.5, 10.085786),
+            (13.054724, 11.2, 9.345276),
+            (13.04356, 11.3, 9.55644),
+            (13.69089, 11.5, 9.30911),
+            (14.3533, 11.7, 9.0467),
+            (14.46125, 11.9, 9.33875),
+        ];
+        assert_bands(&values, 4, &expected);
+    }
+
+    #[test]
+    fn donchian_tracks_window_extremes() {
+        let values = Donchian::new(3).batch(&sample_klines());
+        let expected = [
+            (12., 10.5, 9.),
+            (12., 10.75, 9.5),
+            (12.5, 11.25, 10.),
+            (13., 11.5, 10.),
+            (13., 11.75, 10.5),
+            (13., 11.25, 9.5),
+            (12., 10.75, 9.5),
+            (13.5, 11.5, 9.5),
+            (14., 12., 10.),
+            (14., 12.75, 11.5),
+        ];
+        assert_bands(&values, 2, &expected);
+    }
+
+    #[test]
+    fn keltner_matches_reference() {
+        let values = Keltner::new(3, 3, 2.).batch(&sample_klines());
+        let expected = [
+            (13.166667, 10.5, 7.833333),
+            (13.277778, 10.5, 7.722222),
+            (14.435185, 11.25, 8.064815),
+            (14.665123, 11.875, 9.084877),
+            (14.630916, 11.4375, 8.244084),
+            (13.847694, 10.71875, 7.589806),
+            (13.945338, 10.859375, 7.773412),
+            (15.653663, 11.929688, 8.205712),
+            (16.197494, 12.714844, 9.232194),
+            (16.012522, 12.357422, 8.702322),
+        ];
+        assert_bands(&values, 2, &expected);
+    }
+}
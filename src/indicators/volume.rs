@@ -0,0 +1,108 @@
+use crate::indicators::base::Indicator;
+use crate::types::interval::KlineInterval;
+use crate::types::kline::Kline;
+
+/// On balance volume, starting at 0 on the first kline.
+#[derive(Debug, Clone, Default)]
+pub struct Obv {
+    prev_close: Option<f64>,
+    value: f64,
+}
+
+impl Obv {
+    pub fn new() -> Obv {
+        Obv::default()
+    }
+}
+
+impl Indicator for Obv {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        if let Some(prev_close) = self.prev_close {
+            if kline.close > prev_close {
+                self.value += kline.volume;
+            } else if kline.close < prev_close {
+                self.value -= kline.volume;
+            }
+        }
+        self.prev_close = Some(kline.close);
+        Some(self.value)
+    }
+}
+
+/// Volume weighted typical price, restarting at every `session` boundary,
+/// e.g. `Day1` for the daily VWAP, or never without one.
+#[derive(Debug, Clone)]
+pub struct Vwap {
+    session: Option<KlineInterval>,
+    session_open_ts: i64,
+    price_volume: f64,
+    volume: f64,
+}
+
+impl Vwap {
+    pub fn new(session: Option<KlineInterval>) -> Vwap {
+        Vwap {
+            session,
+            session_open_ts: i64::MIN,
+            price_volume: 0.,
+            volume: 0.,
+        }
+    }
+}
+
+impl Indicator for Vwap {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        if let Some(session) = self.session {
+            // Only `Month1` sessions can fail, on timestamps chrono can't represent
+            let (open_ts, _) = session.bucket(kline.open_timestamp).ok()?;
+            if open_ts != self.session_open_ts {
+                self.session_open_ts = open_ts;
+                self.price_volume = 0.;
+                self.volume = 0.;
+            }
+        }
+        let typical_price = (kline.high + kline.low + kline.close) / 3.;
+        self.price_volume += typical_price * kline.volume;
+        self.volume += kline.volume;
+        (self.volume > 0.).then(|| self.price_volume / self.volume)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indicators::base::tests::{assert_series, sample_klines};
+
+    #[test]
+    fn obv_adds_volume_on_up_closes() {
+        let values = Obv::new().batch(&sample_klines());
+        let expected = [
+            0., 200., 350., 230., 530., 780., 600., 380., 540., 940., 1290., 1090.,
+        ];
+        assert_series(&values, 0, &expected, 0.);
+    }
+
+    #[test]
+    fn vwap_without_session_matches_reference() {
+        let values = Vwap::new(None).batch(&sample_klines());
+        let expected = [
+            9.5, 10.055556, 10.481481, 10.520468, 10.97318, 11.313988, 11.29359, 11.130482,
+            11.102183, 11.403045, 11.68107, 11.717997,
+        ];
+        assert_series(&values, 0, &expected, 1e-6);
+    }
+
+    #[test]
+    fn vwap_restarts_every_session() {
+        let klines = sample_klines();
+        let values = Vwap::new(Some(KlineInterval::Hour4)).batch(&klines);
+        let unsessioned = Vwap::new(None).batch(&klines[4..8]);
+        assert_eq!(values[..4], Vwap::new(None).batch(&klines[..4])[..]);
+        assert_eq!(values[4..8], unsessioned[..]);
+        assert_eq!(values[8], Some((11.5 + 10. + 11.) / 3.));
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{info, warn};
+
+use crate::clients::binance::api::BinanceFuturesApiClient;
+use crate::clients::mongo_client::MongoClient;
+use crate::types::config::{Config, MongoConfig};
+use crate::types::interval::KlineInterval;
+use crate::types::timer::Timer;
+
+pub const KLINE_PAGE_LIMIT: usize = 1500; // max klines per request on binance futures
+pub const FUNDING_PAGE_LIMIT: usize = 1000; // max funding rates per request
+
+pub struct KlineSync {
+    api_client: BinanceFuturesApiClient,
+    mongo_client: MongoClient,
+    mongo_config: MongoConfig,
+}
+
+impl KlineSync {
+    pub async fn new(config: &Config) -> Result<KlineSync> {
+        let mongo_config = config
+            .mongo
+            .clone()
+            .ok_or_else(|| anyhow!("Config has no \"mongo\" section"))?;
+        let mongo_client = MongoClient::new(&mongo_config.connection_string.resolve()?).await?;
+        Ok(KlineSync {
+            api_client: BinanceFuturesApiClient::new("".to_owned(), "".to_owned()),
+            mongo_client,
+            mongo_config,
+        })
+    }
+
+    /// Download the closed klines after the newest stored one and upsert them.
+    /// Returns the number of klines written.
+    pub async fn sync(
+        &self,
+        symbol: &str,
+        interval: KlineInterval,
+        start_ts: i64,
+    ) -> Result<usize> {
+        let database = &self.mongo_config.database;
+        let collection = self.mongo_config.kline_collection(symbol, interval);
+        let latest = self
+            .mongo_client
+            .get_latest_close_time(database, &collection)
+            .await?;
+        let mut from_ts = latest.map_or(start_ts, |ts| ts + 1);
+        let limit = KLINE_PAGE_LIMIT.to_string();
+        let mut synced = 0;
+        loop {
+            let now = Utc::now().timestamp_millis();
+            let klines = self
+                .api_client
+                .get_klines(
+                    symbol,
+                    interval,
+                    Some(&from_ts.to_string()),
+                    None,
+                    Some(&limit),
+                )
+                .await?;
+            let fetched = klines.len();
+            // The last kline may still be open
+            let closed = klines
+                .into_iter()
+                .filter(|k| k.close_timestamp < now)
+                .collect::<Vec<_>>();
+            if let Some(last) = closed.last() {
+                from_ts = last.close_timestamp + 1;
+                self.mongo_client
+                    .upsert_klines(database, &collection, &closed)
+                    .await?;
+                synced += closed.len();
+            }
+            if fetched < KLINE_PAGE_LIMIT || closed.is_empty() {
+                break;
+            }
+        }
+        info!("Synced {} klines into {}.{}", synced, database, collection);
+        Ok(synced)
+    }
+
+    /// Download the funding rates after the newest stored one and upsert
+    /// them. Returns the number of rates written.
+    pub async fn sync_funding(&self, symbol: &str, start_ts: i64) -> Result<usize> {
+        let database = &self.mongo_config.database;
+        let collection = self.mongo_config.funding_collection(symbol);
+        let latest = self
+            .mongo_client
+            .get_latest_funding_time(database, &collection)
+            .await?;
+        let mut from_ts = latest.map_or(start_ts, |ts| ts + 1);
+        let mut synced = 0;
+        loop {
+            let rates = self
+                .api_client
+                .get_funding_rates(symbol, Some(from_ts), None, Some(FUNDING_PAGE_LIMIT))
+                .await?;
+            if let Some(last) = rates.last() {
+                from_ts = last.funding_time + 1;
+                self.mongo_client
+                    .upsert_funding_rates(database, &collection, &rates)
+                    .await?;
+                synced += rates.len();
+            }
+            if rates.len() < FUNDING_PAGE_LIMIT {
+                break;
+            }
+        }
+        info!(
+            "Synced {} funding rates into {}.{}",
+            synced, database, collection
+        );
+        Ok(synced)
+    }
+
+    /// Build the kline indexes once before syncing. A collection holding
+    /// duplicates keeps syncing without them until it is repaired.
+    pub async fn create_indexes(&self, config: &Config) {
+        let database = &self.mongo_config.database;
+        for symbol in config.symbols.iter() {
+            for interval in config.sync_intervals() {
+                let collection = self.mongo_config.kline_collection(symbol, interval);
+                if let Err(e) = self
+                    .mongo_client
+                    .create_kline_indexes(database, &collection)
+                    .await
+                {
+                    warn!(
+                        "Couldn't index {}.{}, repair its duplicates: {}",
+                        database, collection, e
+                    );
+                }
+            }
+        }
+    }
+
+    pub async fn sync_all(&self, config: &Config) -> Result<()> {
+        for symbol in config.symbols.iter() {
+            for interval in config.sync_intervals() {
+                if let Err(e) = self.sync(symbol, interval, config.sync.start_ts).await {
+                    warn!("Sync {} {} failed: {:?}", symbol, interval, e);
+                }
+            }
+            if config.sync.funding {
+                if let Err(e) = self.sync_funding(symbol, config.sync.start_ts).await {
+                    warn!("Sync {} funding failed: {:?}", symbol, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sync once, then again on every tick of the sync `schedule` if set.
+    pub async fn run(&self, config: &Config) -> Result<()> {
+        self.create_indexes(config).await;
+        self.sync_all(config).await?;
+        if let Some(schedule) = config.sync.schedule.clone() {
+            let mut timer = Timer::new(schedule).with_offset(config.sync.offset_ms);
+            loop {
+                timer.tick().await;
+                self.sync_all(config).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1 @@
+pub mod kline_sync;
@@ -1,2 +1,11 @@
+pub mod backtest;
 pub mod clients;
+pub mod data;
+pub mod hypertune;
+pub mod indicators;
+pub mod jobs;
+pub mod live;
+pub mod portfolio;
+pub mod risk;
+pub mod strategy;
 pub mod types;
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+use crate::types::config::{MongoConfig, RiskLimits};
+use crate::types::interval::KlineInterval;
+use crate::types::timer::FixedUpdate;
+
+/// Built from the top level `Config` by `Config::live_config`. The keys are
+/// redacted from the `Debug` output.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LiveConfig {
+    pub api_key: String,
+    pub secret_key: String,
+    pub symbol: String,
+    pub interval: KlineInterval,
+    pub fixed_update: Option<FixedUpdate>, // none to run on every kline close
+    pub history_limit: u64,                // klines fetched on start
+    pub poll_interval_ms: u64,
+    pub fee_rate: f64, // estimates the fee when the trades of an order can't be read
+    pub strategy: Value,
+    pub mongo: Option<MongoConfig>, // journal trades, orders and account snapshots
+    #[serde(default)]
+    pub risk: RiskLimits, // checked before every order
+}
+
+impl fmt::Debug for LiveConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LiveConfig")
+            .field("api_key", &"<redacted>")
+            .field("secret_key", &"<redacted>")
+            .field("symbol", &self.symbol)
+            .field("interval", &self.interval)
+            .field("fixed_update", &self.fixed_update)
+            .field("history_limit", &self.history_limit)
+            .field("poll_interval_ms", &self.poll_interval_ms)
+            .field("fee_rate", &self.fee_rate)
+            .field("strategy", &self.strategy)
+            .field("mongo", &self.mongo)
+            .field("risk", &self.risk)
+            .finish()
+    }
+}
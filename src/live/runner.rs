@@ -0,0 +1,688 @@
+use anyhow::{anyhow, Result};
+use async_std::task;
+use chrono::Utc;
+use log::{error, info, warn};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clients::binance::api::{BinanceFuturesApiClient, SYMBOL_TO_INSTRUMENT_INFO};
+use crate::clients::mongo_client::MongoClient;
+use crate::live::config::LiveConfig;
+use crate::risk::kill_switch::KillSwitch;
+use crate::risk::manager::{RiskManager, RiskRejection};
+use crate::strategy::base::Strategy;
+use crate::strategy::context::{OrderRequest, StrategyContext};
+use crate::types::account::{Account, AccountSnapshot};
+use crate::types::config::MongoConfig;
+use crate::types::kline::Kline;
+use crate::types::order::{Fill, Order, OrderRecord, OrderSide};
+use crate::types::timer::Timer;
+use crate::types::trade::{IntrabarOrder, Trade, TradeSide};
+
+enum TradeUpdate {
+    Insert(Trade),
+    Update(Trade),
+}
+
+pub struct LiveRunner {
+    config: LiveConfig,
+    api_client: BinanceFuturesApiClient,
+    journal: Option<(MongoClient, MongoConfig)>,
+    open_trades: Vec<Trade>,
+    last_close_ts: i64,
+    risk_manager: RiskManager,
+    running: Arc<AtomicBool>,
+}
+
+impl LiveRunner {
+    pub fn new(config: LiveConfig) -> LiveRunner {
+        let api_client =
+            BinanceFuturesApiClient::new(config.api_key.clone(), config.secret_key.clone());
+        LiveRunner {
+            risk_manager: RiskManager::new(config.risk.clone()),
+            config,
+            api_client,
+            journal: None,
+            open_trades: Vec::new(),
+            last_close_ts: 0,
+            running: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Drive the strategy until SIGINT or SIGTERM. With a `fixed_update` the
+    /// klines closed since the last tick are fed to `on_kline` on every timer
+    /// tick, followed by `on_timer`. Without one, each kline is fed as soon as
+    /// it closes.
+    pub async fn run<S: Strategy>(mut self, strategy: &mut S) -> Result<()> {
+        let running = self.running.clone();
+        ctrlc::set_handler(move || {
+            info!("Shutdown signal received");
+            running.store(false, Ordering::SeqCst);
+        })?;
+        if let Some(mongo_config) = self.config.mongo.clone() {
+            self.open_journal(mongo_config).await?;
+        }
+
+        let history = self.closed_klines(Some(self.config.history_limit)).await?;
+        self.last_close_ts = history.last().map_or(0, |k| k.close_timestamp);
+        info!(
+            "Loaded {} klines for {} {}, last close: {}",
+            history.len(),
+            self.config.symbol,
+            self.config.interval,
+            self.last_close_ts
+        );
+        let mut ctx = self.context().await?;
+        self.risk_manager
+            .update_equity(ctx.account.equity(), ctx.ts);
+        strategy.on_start(&mut ctx);
+        self.execute(strategy, ctx.take_orders()).await?;
+        // One account request for the whole replay, the account can't change
+        // while orders are ignored
+        let mut ctx = self.context().await?;
+        for kline in history.iter() {
+            strategy.on_kline(kline, &mut ctx);
+            let orders = ctx.take_orders();
+            if !orders.is_empty() {
+                warn!(
+                    "Ignore {} orders submitted while replaying history",
+                    orders.len()
+                );
+            }
+        }
+
+        let mut timer = self.config.fixed_update.clone().map(Timer::new);
+        while self.running.load(Ordering::SeqCst) {
+            let now = Utc::now().timestamp_millis();
+            let tick_ts = match timer.as_mut() {
+                Some(timer) => timer.update().then(|| timer.get_ts_ms()),
+                None => {
+                    // Close time of the kline following the last one seen
+                    let (_, next_close_ts) = self.config.interval.bucket(self.last_close_ts + 1)?;
+                    (now > next_close_ts).then_some(now)
+                }
+            };
+            if let Some(ts) = tick_ts {
+                if let Err(e) = self.tick(strategy, ts, timer.is_some()).await {
+                    warn!("Tick failed: {:?}", e);
+                }
+                if timer.is_none() {
+                    // Wait for the next boundary even if no new kline arrived
+                    let (open_ts, _) = self.config.interval.bucket(now)?;
+                    self.last_close_ts = self.last_close_ts.max(open_ts - 1);
+                }
+            }
+            task::sleep(Duration::from_millis(self.config.poll_interval_ms)).await;
+        }
+
+        let mut ctx = self.context().await?;
+        strategy.on_stop(&mut ctx);
+        self.execute(strategy, ctx.take_orders()).await?;
+        info!(
+            "Live runner stopped with {} open trades",
+            self.open_trades.len()
+        );
+        Ok(())
+    }
+
+    /// Connect to the journal and restore the trades left open by a previous run.
+    async fn open_journal(&mut self, mongo_config: MongoConfig) -> Result<()> {
+        let mongo_client = MongoClient::new(&mongo_config.connection_string.resolve()?).await?;
+        let database = &mongo_config.database;
+        mongo_client
+            .create_journal_indexes(
+                database,
+                &mongo_config.trade_collection,
+                &mongo_config.order_collection,
+                &mongo_config.account_collection,
+            )
+            .await?;
+        self.open_trades = mongo_client
+            .get_open_trades(
+                database,
+                &mongo_config.trade_collection,
+                Some(&self.config.symbol),
+            )
+            .await?;
+        info!(
+            "Restored {} open trades from {}.{}",
+            self.open_trades.len(),
+            database,
+            mongo_config.trade_collection
+        );
+        self.journal = Some((mongo_client, mongo_config));
+        Ok(())
+    }
+
+    /// Storage failures are logged but never stop trading.
+    async fn journal_trades(&self, updates: Vec<TradeUpdate>) {
+        let (mongo_client, mongo_config) = match &self.journal {
+            Some(journal) => journal,
+            None => return,
+        };
+        let database = &mongo_config.database;
+        let collection = &mongo_config.trade_collection;
+        for update in updates {
+            let result = match &update {
+                TradeUpdate::Insert(trade) => {
+                    mongo_client.insert_trade(database, collection, trade).await
+                }
+                TradeUpdate::Update(trade) => {
+                    mongo_client.upsert_trade(database, collection, trade).await
+                }
+            };
+            if let Err(e) = result {
+                warn!("Journal trade failed: {}", e);
+            }
+        }
+    }
+
+    async fn journal_order(&self, record: OrderRecord) {
+        if let Some((mongo_client, mongo_config)) = &self.journal {
+            if let Err(e) = mongo_client
+                .insert_order_record(
+                    &mongo_config.database,
+                    &mongo_config.order_collection,
+                    &record,
+                )
+                .await
+            {
+                warn!("Journal order failed: {}", e);
+            }
+        }
+    }
+
+    async fn journal_account(&self, account: &Account) {
+        if let Some((mongo_client, mongo_config)) = &self.journal {
+            let snapshot = AccountSnapshot {
+                ts: Utc::now().timestamp_millis(),
+                account: account.clone(),
+            };
+            if let Err(e) = mongo_client
+                .insert_account_snapshot(
+                    &mongo_config.database,
+                    &mongo_config.account_collection,
+                    &snapshot,
+                )
+                .await
+            {
+                warn!("Journal account failed: {}", e);
+            }
+        }
+    }
+
+    async fn tick<S: Strategy>(&mut self, strategy: &mut S, ts: i64, on_timer: bool) -> Result<()> {
+        let last_close_ts = self.last_close_ts;
+        let klines = self.closed_klines(Some(10)).await?;
+        for kline in klines.iter().filter(|k| k.close_timestamp > last_close_ts) {
+            info!("New kline: {:?}", kline);
+            self.last_close_ts = kline.close_timestamp;
+            self.check_exits(strategy, kline).await?;
+            let mut ctx = self.context().await?;
+            strategy.on_kline(kline, &mut ctx);
+            self.execute(strategy, ctx.take_orders()).await?;
+        }
+        if on_timer {
+            let mut ctx = self.context().await?;
+            strategy.on_timer(ts, &mut ctx);
+            self.execute(strategy, ctx.take_orders()).await?;
+        }
+        self.reconcile().await
+    }
+
+    /// Klines whose close time has passed, the still open one is dropped.
+    async fn closed_klines(&self, limit: Option<u64>) -> Result<Vec<Kline>> {
+        let limit = limit.map(|l| l.to_string());
+        let now = Utc::now().timestamp_millis();
+        let klines = self
+            .api_client
+            .get_klines(
+                &self.config.symbol,
+                self.config.interval,
+                None,
+                None,
+                limit.as_deref(),
+            )
+            .await?;
+        Ok(klines
+            .into_iter()
+            .filter(|k| k.close_timestamp < now)
+            .collect())
+    }
+
+    async fn context(&self) -> Result<StrategyContext> {
+        let account = self.api_client.get_account().await?;
+        Ok(StrategyContext::new(
+            Utc::now().timestamp_millis(),
+            account,
+            self.open_trades.clone(),
+        ))
+    }
+
+    /// Take profit and stop loss are evaluated on closed klines and exited
+    /// with reduce only market orders.
+    async fn check_exits<S: Strategy>(&mut self, strategy: &mut S, kline: &Kline) -> Result<()> {
+        let mut exits = Vec::new();
+        for trade in self.open_trades.iter() {
+            // Only the closed kline is known, whichever level was hit exits
+            if let Some((reason, _)) = trade.exit_hit(kline, IntrabarOrder::StopFirst) {
+                info!(
+                    "Exit {:?} {} {} entered at {}, {:?} hit",
+                    trade.entry_side, trade.position, trade.symbol, trade.entry_price, reason
+                );
+                let is_long = trade.entry_side == TradeSide::Buy;
+                let side = if is_long {
+                    OrderSide::Sell
+                } else {
+                    OrderSide::Buy
+                };
+                let mut order = Order::market_order(trade.symbol.clone(), side, trade.position);
+                order.reduce_only = true;
+                exits.push(OrderRequest {
+                    order,
+                    tp_price: 0.,
+                    sl_price: 0.,
+                });
+            }
+        }
+        self.execute(strategy, exits).await
+    }
+
+    async fn execute<S: Strategy>(
+        &mut self,
+        strategy: &mut S,
+        orders: Vec<OrderRequest>,
+    ) -> Result<()> {
+        let mut pending = orders;
+        while !pending.is_empty() {
+            let mut next = Vec::new();
+            for request in pending {
+                let (order_id, fill) = match self.place_order(&request).await {
+                    Ok(placed) => placed,
+                    Err(e) => {
+                        warn!("Order {:?} rejected: {:?}", request.order, e);
+                        continue;
+                    }
+                };
+                let updates = self.record_fill(order_id, &fill, &request);
+                self.journal_trades(updates).await;
+                let mut ctx = self.context().await?;
+                strategy.on_fill(&fill, &mut ctx);
+                next.extend(ctx.take_orders());
+            }
+            pending = next;
+        }
+        Ok(())
+    }
+
+    /// Returns the exchange's order id with the fill.
+    async fn place_order(&mut self, request: &OrderRequest) -> Result<(i64, Fill)> {
+        let order = request.order.clone();
+        let instrument_info = SYMBOL_TO_INSTRUMENT_INFO
+            .get(&order.symbol)
+            .ok_or_else(|| anyhow!("Unknown symbol {}", order.symbol))?;
+        info!(
+            "Submit {:?} tp: {} sl: {}",
+            order, request.tp_price, request.sl_price
+        );
+        let mut record = OrderRecord {
+            ts: Utc::now().timestamp_millis(),
+            symbol: order.symbol.clone(),
+            order: order.clone(),
+            tp_price: request.tp_price,
+            sl_price: request.sl_price,
+            response: None,
+            error: None,
+        };
+        // Reduce only orders skip the price checks
+        if !order.reduce_only {
+            let mark_price = self.api_client.get_mark_price(&order.symbol).await?;
+            self.risk_manager
+                .update_mark_price(&order.symbol, mark_price);
+            if order.price.is_none() && self.config.risk.max_price_deviation.is_some() {
+                let price = self.api_client.get_price(&order.symbol).await?;
+                self.risk_manager.update_last_price(&order.symbol, price);
+            }
+        }
+        let account = self.api_client.get_account().await?;
+        if let Err(rejection) = self.risk_manager.check(&order, &account, record.ts) {
+            record.error = Some(rejection.to_string());
+            self.journal_order(record).await;
+            if self.config.risk.kill_on_daily_loss
+                && matches!(rejection, RiskRejection::DailyLoss { .. })
+            {
+                self.kill(&rejection.to_string()).await;
+            }
+            return Err(rejection.into());
+        }
+        let response = match self
+            .api_client
+            .place_order(order.clone(), instrument_info)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                record.error = Some(e.to_string());
+                self.journal_order(record).await;
+                return Err(e);
+            }
+        };
+        info!("Order response: {}", response);
+        record.response = Some(response.clone());
+        self.journal_order(record).await;
+        if let Some(code) = response.get("code") {
+            return Err(anyhow!("Binance error {}: {}", code, response["msg"]));
+        }
+        let order_id = response["orderId"]
+            .as_i64()
+            .ok_or_else(|| anyhow!("Order response without orderId: {}", response))?;
+        let (executed_qty, avg_price) = match fill_of(&response) {
+            Some(fill) => fill,
+            // Not filled yet when the response was built, ask for the order again
+            None => {
+                let status = self.api_client.get_order(&order.symbol, order_id).await?;
+                fill_of(&status).ok_or_else(|| {
+                    anyhow!("Order {} has no known fill price: {}", order_id, status)
+                })?
+            }
+        };
+        let fee = self
+            .order_fee(&order.symbol, order_id, executed_qty * avg_price)
+            .await;
+        let fill = Fill {
+            symbol: order.symbol,
+            order_side: order.order_side,
+            size: executed_qty,
+            price: avg_price,
+            fee,
+            ts: response["updateTime"]
+                .as_i64()
+                .unwrap_or_else(|| Utc::now().timestamp_millis()),
+        };
+        Ok((order_id, fill))
+    }
+
+    /// Commission paid on the order's trades, estimated from the configured
+    /// fee rate if they can't be read or were paid in another asset.
+    async fn order_fee(&self, symbol: &str, order_id: i64, notional: f64) -> f64 {
+        let commission = match self.api_client.get_user_trades(symbol, order_id).await {
+            Ok(trades) => commission_of(&trades, symbol),
+            Err(e) => {
+                warn!("Couldn't read the trades of order {}: {:?}", order_id, e);
+                None
+            }
+        };
+        commission.unwrap_or_else(|| {
+            let fee = notional * self.config.fee_rate;
+            warn!("Estimate the fee of order {} as {}", order_id, fee);
+            fee
+        })
+    }
+
+    /// Net the fill against opposite open trades first, the remainder opens a
+    /// new trade unless the order is reduce only. Partially closed trades are
+    /// split into a closed and an open part. Trade ids are built from the
+    /// order ids, the opening one and for closed parts also the closing one.
+    fn record_fill(
+        &mut self,
+        order_id: i64,
+        fill: &Fill,
+        request: &OrderRequest,
+    ) -> Vec<TradeUpdate> {
+        let side = match fill.order_side {
+            OrderSide::Buy => TradeSide::Buy,
+            OrderSide::Sell => TradeSide::Sell,
+        };
+        let mut updates = Vec::new();
+        let mut remaining = fill.size;
+        for trade in self.open_trades.iter_mut() {
+            if remaining <= 0. || trade.symbol != fill.symbol || trade.entry_side == side {
+                continue;
+            }
+            let closed = trade.position.min(remaining);
+            remaining -= closed;
+            let fee = fill.fee * closed / fill.size;
+            let mut closed_trade = trade.close_partial(closed, fill.price, fill.ts, fee);
+            info!(
+                "Closed {} {} entered at {}, exit at {}, pnl: {}",
+                closed,
+                trade.symbol,
+                trade.entry_price,
+                fill.price,
+                closed_trade.realized_pnl()
+            );
+            if trade.is_open() {
+                closed_trade.id = format!("{}-{}", trade.id, order_id);
+                updates.push(TradeUpdate::Update(trade.clone()));
+                updates.push(TradeUpdate::Insert(closed_trade));
+            } else {
+                updates.push(TradeUpdate::Update(closed_trade));
+            }
+        }
+        self.open_trades.retain(|t| t.is_open());
+        if remaining > 0. && !request.order.reduce_only {
+            info!(
+                "Opened {:?} {} {} at {}",
+                side, remaining, fill.symbol, fill.price
+            );
+            let fee = fill.fee * remaining / fill.size;
+            let mut trade = Trade::open(
+                fill.symbol.clone(),
+                side,
+                remaining,
+                fill.price,
+                fill.ts,
+                fee,
+            );
+            trade.id = format!("{}-{}", fill.symbol, order_id);
+            trade.tp_price = request.tp_price;
+            trade.sl_price = request.sl_price;
+            updates.push(TradeUpdate::Insert(trade.clone()));
+            self.open_trades.push(trade);
+        }
+        updates
+    }
+
+    /// Flatten the account and stop the runner, the next reconcile drops
+    /// the local trades once the exchange is flat.
+    async fn kill(&mut self, reason: &str) {
+        let mut kill_switch = KillSwitch::new(self.api_client.clone());
+        if let Some((mongo_client, mongo_config)) = &self.journal {
+            kill_switch = kill_switch.with_journal(mongo_client.clone(), mongo_config.clone());
+        }
+        let report = kill_switch.trigger(reason).await;
+        if !report.flat {
+            error!("Account isn't flat after the kill switch, check it manually");
+        }
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Compare the locally tracked trades with the exchange positions. Trades
+    /// of a symbol the exchange reports as flat are closed at the last price.
+    async fn reconcile(&mut self) -> Result<()> {
+        let account: Account = self.api_client.get_account().await?;
+        self.journal_account(&account).await;
+        self.risk_manager
+            .update_equity(account.equity(), Utc::now().timestamp_millis());
+        let symbol = self.config.symbol.clone();
+        let local: f64 = self
+            .open_trades
+            .iter()
+            .filter(|t| t.symbol == symbol)
+            .map(|t| t.position * t.entry_side.value())
+            .sum();
+        let remote = account
+            .positions
+            .iter()
+            .filter(|p| p.symbol == symbol)
+            .map(|p| p.position_amt)
+            .sum::<f64>();
+        if (local - remote).abs() > f64::EPSILON * local.abs().max(1.) {
+            warn!(
+                "Position mismatch for {}, local: {}, exchange: {}",
+                symbol, local, remote
+            );
+            if remote == 0. {
+                // Closed outside of the runner, exit at the current price with
+                // an estimated fee. Without a price the trades are kept for the
+                // next reconcile rather than journaled without an exit price.
+                let price = match self.api_client.get_price(&symbol).await {
+                    Ok(price) => price,
+                    Err(e) => {
+                        warn!(
+                            "Keep the local trades of {} until a price is known: {:?}",
+                            symbol, e
+                        );
+                        return Ok(());
+                    }
+                };
+                let now = Utc::now().timestamp_millis();
+                let (dropped, kept) = std::mem::take(&mut self.open_trades)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|t| t.symbol == symbol);
+                self.open_trades = kept;
+                warn!(
+                    "Closed {} local trades of {} at {}",
+                    dropped.len(),
+                    symbol,
+                    price
+                );
+                let updates = dropped
+                    .into_iter()
+                    .map(|mut trade| {
+                        let fee = price * trade.position * self.config.fee_rate;
+                        trade.close(price, now, fee);
+                        TradeUpdate::Update(trade)
+                    })
+                    .collect();
+                self.journal_trades(updates).await;
+            }
+        } else {
+            info!("Position of {} reconciled: {}", symbol, remote);
+        }
+        Ok(())
+    }
+}
+
+/// Executed quantity and average price of an order response, none until
+/// some of it filled.
+fn fill_of(response: &Value) -> Option<(f64, f64)> {
+    let parse = |key: &str| -> Option<f64> {
+        response[key]
+            .as_str()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| *v > 0.)
+    };
+    Some((parse("executedQty")?, parse("avgPrice")?))
+}
+
+/// Total commission of an order's trades, none if there are no trades or a
+/// commission was paid in an asset other than the symbol's quote, e.g. BNB.
+fn commission_of(trades: &Value, symbol: &str) -> Option<f64> {
+    let trades = trades.as_array().filter(|trades| !trades.is_empty())?;
+    let mut total = 0.;
+    for trade in trades {
+        let asset = trade["commissionAsset"].as_str()?;
+        if !symbol.ends_with(asset) {
+            return None;
+        }
+        total += trade["commission"].as_str()?.parse::<f64>().ok()?;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::config::RiskLimits;
+    use crate::types::interval::KlineInterval;
+    use serde_json::json;
+
+    #[test]
+    fn fill_of_needs_quantity_and_price() {
+        let response = json!({"executedQty": "0.010", "avgPrice": "20000.50"});
+        assert_eq!(fill_of(&response), Some((0.01, 20000.5)));
+        let ack = json!({"executedQty": "0", "avgPrice": "0.00000"});
+        assert_eq!(fill_of(&ack), None);
+        assert_eq!(fill_of(&json!({"orderId": 1})), None);
+    }
+
+    fn runner() -> LiveRunner {
+        LiveRunner::new(LiveConfig {
+            api_key: String::new(),
+            secret_key: String::new(),
+            symbol: "BTCUSDT".to_owned(),
+            interval: KlineInterval::Minute1,
+            fixed_update: None,
+            history_limit: 0,
+            poll_interval_ms: 1000,
+            fee_rate: 0.0005,
+            strategy: Value::Null,
+            mongo: None,
+            risk: RiskLimits::default(),
+        })
+    }
+
+    fn market(order_side: OrderSide, size: f64) -> (Fill, OrderRequest) {
+        let fill = Fill {
+            symbol: "BTCUSDT".to_owned(),
+            order_side: order_side.clone(),
+            size,
+            price: 20000.,
+            fee: 0.,
+            ts: 1,
+        };
+        let order = Order::market_order("BTCUSDT".to_owned(), order_side, size);
+        let request = OrderRequest {
+            order,
+            tp_price: 0.,
+            sl_price: 0.,
+        };
+        (fill, request)
+    }
+
+    fn ids(updates: &[TradeUpdate]) -> Vec<(bool, &str)> {
+        updates
+            .iter()
+            .map(|update| match update {
+                TradeUpdate::Insert(trade) => (true, trade.id.as_str()),
+                TradeUpdate::Update(trade) => (false, trade.id.as_str()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trades_are_journaled_under_order_ids() {
+        let mut runner = runner();
+        let (fill, request) = market(OrderSide::Buy, 2.);
+        let updates = runner.record_fill(11, &fill, &request);
+        assert_eq!(ids(&updates), [(true, "BTCUSDT-11")]);
+
+        let (fill, request) = market(OrderSide::Sell, 0.5);
+        let updates = runner.record_fill(12, &fill, &request);
+        assert_eq!(
+            ids(&updates),
+            [(false, "BTCUSDT-11"), (true, "BTCUSDT-11-12")]
+        );
+
+        let (fill, request) = market(OrderSide::Sell, 1.5);
+        let updates = runner.record_fill(13, &fill, &request);
+        assert_eq!(ids(&updates), [(false, "BTCUSDT-11")]);
+        assert!(runner.open_trades.is_empty());
+    }
+
+    #[test]
+    fn commission_of_sums_quote_commissions() {
+        let trades = json!([
+            {"commission": "0.04000000", "commissionAsset": "USDT"},
+            {"commission": "0.01000000", "commissionAsset": "USDT"},
+        ]);
+        assert!((commission_of(&trades, "BTCUSDT").unwrap() - 0.05).abs() < 1e-12);
+        let bnb = json!([{"commission": "0.0001", "commissionAsset": "BNB"}]);
+        assert_eq!(commission_of(&bnb, "BTCUSDT"), None);
+        assert_eq!(commission_of(&json!([]), "BTCUSDT"), None);
+        assert_eq!(commission_of(&json!({"code": -1021}), "BTCUSDT"), None);
+    }
+}
@@ -1,7 +1,68 @@
+use anyhow::{anyhow, Result};
 use clap::Parser;
-use trade_utils::types::cli::Cli;
+use log::{info, LevelFilter};
+use simplelog::{ColorChoice, TermLogger, TerminalMode};
+use trade_utils::backtest::data;
+use trade_utils::backtest::engine::Backtester;
+use trade_utils::backtest::report::MetricsReport;
+use trade_utils::hypertune::tuner::Hypertuner;
+use trade_utils::jobs::kline_sync::KlineSync;
+use trade_utils::live::runner::LiveRunner;
+use trade_utils::risk::kill_switch::KillSwitch;
+use trade_utils::strategy::registry::{build_strategy, with_params};
+use trade_utils::types::cli::{Cli, Mode};
+use trade_utils::types::config::Config;
 
-fn main() {
+#[async_std::main]
+async fn main() -> Result<()> {
+    TermLogger::init(
+        LevelFilter::Info,
+        simplelog::Config::default(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    )?;
     let args = Cli::parse();
-    println!("args: {:?}", args);
+    // Overrides may hold secrets
+    info!("Mode {:?} with config {:?}", args.mode, args.config_path);
+    let config = Config::from_path(&args.config_path, &args.overrides)?;
+    match args.mode {
+        Mode::Backtest => {
+            let symbol = config.backtest_symbol()?;
+            let klines = data::load_klines(&config).await?;
+            let funding_rates = data::load_funding_rates(&config).await?;
+            let mut strategy = build_strategy(symbol, &config.strategy)?;
+            let result = Backtester::new(config.backtest_config())
+                .with_funding_rates(funding_rates)
+                .run(&klines, &mut strategy);
+            println!("{}", MetricsReport::from_result(&result));
+        }
+        Mode::Hypertune => {
+            let symbol = config.backtest_symbol()?;
+            let klines = data::load_klines(&config).await?;
+            let funding_rates = data::load_funding_rates(&config).await?;
+            let hypertuner =
+                Hypertuner::new(config.hypertune_config()?).with_funding_rates(funding_rates);
+            let runs = hypertuner.run(&klines, |params| {
+                build_strategy(symbol, &with_params(&config.strategy, params))
+            })?;
+            hypertuner.save(&runs).await?;
+        }
+        Mode::Live => {
+            let live_config = config.live_config()?;
+            let mut strategy = build_strategy(&live_config.symbol, &live_config.strategy)?;
+            LiveRunner::new(live_config).run(&mut strategy).await?;
+        }
+        Mode::Sync => KlineSync::new(&config).await?.run(&config).await?,
+        Mode::Kill => {
+            let report = KillSwitch::from_config(&config)
+                .await?
+                .trigger("Manual kill from the command line")
+                .await;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.flat {
+                return Err(anyhow!("Positions are still open"));
+            }
+        }
+    }
+    Ok(())
 }
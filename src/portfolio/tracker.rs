@@ -0,0 +1,306 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::types::account::Position;
+use crate::types::order::{Fill, OrderSide};
+
+// Quantities below this are treated as flat
+const QTY_EPSILON: f64 = 1e-9;
+
+/// Net position of a symbol built from fills.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackedPosition {
+    pub symbol: String,
+    pub quantity: f64,     // negative when short
+    pub entry_price: f64,  // average over the open quantity, 0 when flat
+    pub realized_pnl: f64, // before fees
+    pub fees: f64,
+    pub last_update_ts: i64,
+}
+
+impl TrackedPosition {
+    pub fn is_flat(&self) -> bool {
+        self.quantity.abs() < QTY_EPSILON
+    }
+
+    pub fn unrealized_pnl(&self, mark_price: f64) -> f64 {
+        (mark_price - self.entry_price) * self.quantity
+    }
+
+    /// Realized PnL after fees.
+    pub fn net_realized_pnl(&self) -> f64 {
+        self.realized_pnl - self.fees
+    }
+
+    /// Same shape as the positions of `BinanceFuturesApiClient::get_account`.
+    pub fn to_position(&self, mark_price: f64, leverage: u64) -> Position {
+        Position {
+            symbol: self.symbol.clone(),
+            unrealized_profit: self.unrealized_pnl(mark_price),
+            leverage,
+            entry_price: self.entry_price,
+            position_side: "BOTH".to_owned(),
+            position_amt: self.quantity,
+        }
+    }
+
+    /// Apply a signed quantity, returns the PnL it realized.
+    fn apply(&mut self, quantity: f64, price: f64) -> f64 {
+        let mut realized = 0.;
+        if self.is_flat() || self.quantity.signum() == quantity.signum() {
+            let total = self.quantity.abs() + quantity.abs();
+            self.entry_price =
+                (self.entry_price * self.quantity.abs() + price * quantity.abs()) / total;
+        } else {
+            let closed = self.quantity.abs().min(quantity.abs());
+            realized = (price - self.entry_price) * closed * self.quantity.signum();
+            if quantity.abs() > self.quantity.abs() + QTY_EPSILON {
+                // Flipped, the remainder is opened at the fill price
+                self.entry_price = price;
+            }
+        }
+        self.quantity += quantity;
+        if self.is_flat() {
+            self.quantity = 0.;
+            self.entry_price = 0.;
+        }
+        self.realized_pnl += realized;
+        realized
+    }
+}
+
+/// Difference between a tracked position and the exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionMismatch {
+    pub symbol: String,
+    pub local_quantity: f64,
+    pub exchange_quantity: f64,
+    pub local_entry_price: f64,
+    pub exchange_entry_price: f64,
+}
+
+/// Nets fills into one position per symbol, keeping the average entry price
+/// and the realized PnL and fees, the way a one way mode futures account does.
+#[derive(Debug, Clone, Default)]
+pub struct PositionTracker {
+    positions: BTreeMap<String, TrackedPosition>,
+}
+
+impl PositionTracker {
+    pub fn new() -> PositionTracker {
+        PositionTracker::default()
+    }
+
+    /// Returns the PnL realized by the fill, before fees.
+    pub fn on_fill(&mut self, fill: &Fill) -> f64 {
+        let quantity = match fill.order_side {
+            OrderSide::Buy => fill.size,
+            OrderSide::Sell => -fill.size,
+        };
+        let position = self
+            .positions
+            .entry(fill.symbol.clone())
+            .or_insert_with(|| TrackedPosition {
+                symbol: fill.symbol.clone(),
+                ..Default::default()
+            });
+        position.fees += fill.fee;
+        position.last_update_ts = fill.ts;
+        position.apply(quantity, fill.price)
+    }
+
+    pub fn position(&self, symbol: &str) -> Option<&TrackedPosition> {
+        self.positions.get(symbol)
+    }
+
+    /// All symbols seen, flat ones included for their realized PnL.
+    pub fn positions(&self) -> impl Iterator<Item = &TrackedPosition> {
+        self.positions.values()
+    }
+
+    pub fn open_positions(&self) -> impl Iterator<Item = &TrackedPosition> {
+        self.positions.values().filter(|p| !p.is_flat())
+    }
+
+    pub fn quantity(&self, symbol: &str) -> f64 {
+        self.position(symbol).map_or(0., |p| p.quantity)
+    }
+
+    pub fn realized_pnl(&self) -> f64 {
+        self.positions.values().map(|p| p.realized_pnl).sum()
+    }
+
+    pub fn fees(&self) -> f64 {
+        self.positions.values().map(|p| p.fees).sum()
+    }
+
+    /// Compare with the exchange positions. Entry prices are compared with
+    /// `price_tolerance` relative to the exchange price.
+    pub fn reconcile(&self, positions: &[Position], price_tolerance: f64) -> Vec<PositionMismatch> {
+        let mut exchange = BTreeMap::new();
+        for position in positions.iter() {
+            let entry = exchange.entry(position.symbol.as_str()).or_insert((0., 0.));
+            entry.0 += position.position_amt;
+            entry.1 = position.entry_price;
+        }
+        let mut symbols = exchange.keys().copied().collect::<Vec<_>>();
+        symbols.extend(self.positions.keys().map(|s| s.as_str()));
+        symbols.sort_unstable();
+        symbols.dedup();
+
+        let mut mismatches = Vec::new();
+        for symbol in symbols {
+            let (exchange_quantity, exchange_entry_price) =
+                exchange.get(symbol).copied().unwrap_or((0., 0.));
+            let (local_quantity, local_entry_price) = self
+                .position(symbol)
+                .map_or((0., 0.), |p| (p.quantity, p.entry_price));
+            let quantity_differs = (local_quantity - exchange_quantity).abs() > QTY_EPSILON;
+            let price_differs = exchange_quantity.abs() > QTY_EPSILON
+                && (local_entry_price - exchange_entry_price).abs()
+                    > price_tolerance * exchange_entry_price.abs();
+            if quantity_differs || price_differs {
+                mismatches.push(PositionMismatch {
+                    symbol: symbol.to_owned(),
+                    local_quantity,
+                    exchange_quantity,
+                    local_entry_price,
+                    exchange_entry_price,
+                });
+            }
+        }
+        mismatches
+    }
+
+    /// Take quantity and entry price from the exchange where they differ,
+    /// keeping the realized PnL and fees tracked so far. Returns what changed.
+    pub fn sync_with(
+        &mut self,
+        positions: &[Position],
+        price_tolerance: f64,
+    ) -> Vec<PositionMismatch> {
+        let mismatches = self.reconcile(positions, price_tolerance);
+        for mismatch in mismatches.iter() {
+            warn!(
+                "Position {} tracked as {} at {}, exchange has {} at {}",
+                mismatch.symbol,
+                mismatch.local_quantity,
+                mismatch.local_entry_price,
+                mismatch.exchange_quantity,
+                mismatch.exchange_entry_price
+            );
+            let position = self
+                .positions
+                .entry(mismatch.symbol.clone())
+                .or_insert_with(|| TrackedPosition {
+                    symbol: mismatch.symbol.clone(),
+                    ..Default::default()
+                });
+            position.quantity = mismatch.exchange_quantity;
+            position.entry_price = mismatch.exchange_entry_price;
+        }
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(order_side: OrderSide, size: f64, price: f64) -> Fill {
+        Fill {
+            symbol: "BTCUSDT".to_owned(),
+            order_side,
+            size,
+            price,
+            fee: 0.1,
+            ts: 0,
+        }
+    }
+
+    fn exchange_position(position_amt: f64, entry_price: f64) -> Position {
+        Position {
+            symbol: "BTCUSDT".to_owned(),
+            unrealized_profit: 0.,
+            leverage: 1,
+            entry_price,
+            position_side: "BOTH".to_owned(),
+            position_amt,
+        }
+    }
+
+    #[test]
+    fn adding_averages_the_entry_price() {
+        let mut tracker = PositionTracker::new();
+        assert_eq!(tracker.on_fill(&fill(OrderSide::Buy, 1., 100.)), 0.);
+        assert_eq!(tracker.on_fill(&fill(OrderSide::Buy, 3., 120.)), 0.);
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert_eq!(position.quantity, 4.);
+        assert_eq!(position.entry_price, 115.);
+        assert_eq!(position.unrealized_pnl(125.), 40.);
+
+        let mut tracker = PositionTracker::new();
+        tracker.on_fill(&fill(OrderSide::Sell, 2., 100.));
+        tracker.on_fill(&fill(OrderSide::Sell, 2., 110.));
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert_eq!(position.quantity, -4.);
+        assert_eq!(position.entry_price, 105.);
+        assert_eq!(position.unrealized_pnl(100.), 20.);
+    }
+
+    #[test]
+    fn reducing_realizes_at_the_entry_price() {
+        let mut tracker = PositionTracker::new();
+        tracker.on_fill(&fill(OrderSide::Buy, 4., 115.));
+        assert_eq!(tracker.on_fill(&fill(OrderSide::Sell, 1., 130.)), 15.);
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert_eq!(position.quantity, 3.);
+        assert_eq!(position.entry_price, 115.);
+
+        assert_eq!(tracker.on_fill(&fill(OrderSide::Sell, 3., 110.)), -15.);
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert!(position.is_flat());
+        assert_eq!(position.entry_price, 0.);
+        assert_eq!(position.realized_pnl, 0.);
+        assert!((position.net_realized_pnl() + 0.3).abs() < 1e-12);
+        assert_eq!(tracker.open_positions().count(), 0);
+    }
+
+    #[test]
+    fn flipping_opens_the_remainder_at_the_fill_price() {
+        let mut tracker = PositionTracker::new();
+        tracker.on_fill(&fill(OrderSide::Buy, 2., 100.));
+        assert_eq!(tracker.on_fill(&fill(OrderSide::Sell, 5., 90.)), -20.);
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert_eq!(position.quantity, -3.);
+        assert_eq!(position.entry_price, 90.);
+
+        assert_eq!(tracker.on_fill(&fill(OrderSide::Buy, 4., 80.)), 30.);
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert_eq!(position.quantity, 1.);
+        assert_eq!(position.entry_price, 80.);
+        assert_eq!(tracker.realized_pnl(), 10.);
+        assert!((tracker.fees() - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sync_takes_the_exchange_position() {
+        let mut tracker = PositionTracker::new();
+        tracker.on_fill(&fill(OrderSide::Buy, 2., 100.));
+        tracker.on_fill(&fill(OrderSide::Sell, 1., 110.));
+        assert!(tracker
+            .reconcile(&[exchange_position(1., 100.05)], 0.001)
+            .is_empty());
+
+        let mismatches = tracker.reconcile(&[exchange_position(1., 102.)], 0.001);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(tracker.reconcile(&[], 0.001)[0].exchange_quantity, 0.);
+
+        let mismatches = tracker.sync_with(&[exchange_position(3., 105.)], 0.001);
+        assert_eq!(mismatches[0].local_quantity, 1.);
+        let position = tracker.position("BTCUSDT").unwrap();
+        assert_eq!((position.quantity, position.entry_price), (3., 105.));
+        assert_eq!(position.realized_pnl, 10.);
+    }
+}
@@ -0,0 +1,271 @@
+use anyhow::{anyhow, Result};
+use async_std::task;
+use chrono::Utc;
+use log::{error, info, warn};
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use crate::clients::binance::api::BinanceFuturesApiClient;
+use crate::clients::mongo_client::MongoClient;
+use crate::types::account::Position;
+use crate::types::config::{Config, MongoConfig};
+use crate::types::instrument::InstrumentInfo;
+use crate::types::order::{Order, OrderRecord, OrderSide};
+
+pub const KILL_SWITCH_ATTEMPTS: u32 = 5;
+pub const KILL_SWITCH_RETRY_DELAY_MS: u64 = 2000;
+
+/// What a kill switch run did, journaled to the `kill_switch_collection`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KillSwitchReport {
+    pub ts: i64,
+    pub reason: String,
+    pub cancelled_symbols: Vec<String>,
+    pub orders: Vec<OrderRecord>,
+    pub attempts: u32,
+    pub flat: bool,
+    pub remaining_positions: Vec<Position>, // still open after the last attempt
+    pub errors: Vec<String>,
+}
+
+/// Cancel every open order and close every position with reduce only
+/// market orders, checking the account again until it's flat. Only one way
+/// mode positions are supported.
+pub struct KillSwitch {
+    api_client: BinanceFuturesApiClient,
+    journal: Option<(MongoClient, MongoConfig)>,
+    max_attempts: u32,
+    retry_delay_ms: u64,
+}
+
+impl KillSwitch {
+    pub fn new(api_client: BinanceFuturesApiClient) -> KillSwitch {
+        KillSwitch {
+            api_client,
+            journal: None,
+            max_attempts: KILL_SWITCH_ATTEMPTS,
+            retry_delay_ms: KILL_SWITCH_RETRY_DELAY_MS,
+        }
+    }
+
+    /// Built from the exchange credentials, journaling to MongoDB if the
+    /// config has a "mongo" section.
+    pub async fn from_config(config: &Config) -> Result<KillSwitch> {
+        let api_key = config.exchange.api_key.resolve()?;
+        let secret_key = config.exchange.secret_key.resolve()?;
+        if api_key.is_empty() || secret_key.is_empty() {
+            return Err(anyhow!(
+                "Config \"exchange.api_key\" and \"exchange.secret_key\" are required by the kill switch"
+            ));
+        }
+        let mut kill_switch = KillSwitch::new(BinanceFuturesApiClient::new(api_key, secret_key));
+        if let Some(mongo_config) = config.mongo.clone() {
+            let mongo_client = MongoClient::new(&mongo_config.connection_string.resolve()?).await?;
+            kill_switch = kill_switch.with_journal(mongo_client, mongo_config);
+        }
+        Ok(kill_switch)
+    }
+
+    pub fn with_journal(mut self, mongo_client: MongoClient, mongo_config: MongoConfig) -> Self {
+        self.journal = Some((mongo_client, mongo_config));
+        self
+    }
+
+    pub fn with_retries(mut self, max_attempts: u32, retry_delay_ms: u64) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.retry_delay_ms = retry_delay_ms;
+        self
+    }
+
+    /// Keeps going through failed requests, they end up in the report.
+    pub async fn trigger(&self, reason: &str) -> KillSwitchReport {
+        error!("Kill switch triggered: {}", reason);
+        let mut report = KillSwitchReport {
+            ts: Utc::now().timestamp_millis(),
+            reason: reason.to_owned(),
+            ..Default::default()
+        };
+        self.cancel_orders(&mut report).await;
+
+        let mut instruments = None;
+        while report.attempts < self.max_attempts {
+            report.attempts += 1;
+            if self.update_positions(&mut report).await {
+                if report.flat {
+                    break;
+                }
+                if instruments.is_none() {
+                    match self.api_client.get_instruments().await {
+                        Ok(fetched) => instruments = Some(fetched),
+                        Err(e) => report
+                            .errors
+                            .push(format!("Get exchange info failed: {}", e)),
+                    }
+                }
+                if let Some(instruments) = &instruments {
+                    for position in report.remaining_positions.clone() {
+                        self.close_position(&position, instruments, &mut report)
+                            .await;
+                    }
+                }
+            }
+            task::sleep(Duration::from_millis(self.retry_delay_ms)).await;
+        }
+        // The orders of the last attempt haven't been checked yet
+        if !report.flat {
+            self.update_positions(&mut report).await;
+        }
+
+        if report.flat {
+            info!("Kill switch done after {} attempts", report.attempts);
+        } else {
+            error!(
+                "Kill switch gave up after {} attempts, still open: {:?}",
+                report.attempts, report.remaining_positions
+            );
+        }
+        self.journal(&report).await;
+        report
+    }
+
+    /// Read the open positions into the report, false if the account
+    /// couldn't be read.
+    async fn update_positions(&self, report: &mut KillSwitchReport) -> bool {
+        match self.api_client.get_account().await {
+            Ok(account) => {
+                report.remaining_positions = account
+                    .positions
+                    .into_iter()
+                    .filter(|p| p.position_amt != 0.)
+                    .collect();
+                report.flat = report.remaining_positions.is_empty();
+                true
+            }
+            Err(e) => {
+                report.errors.push(format!("Get account failed: {}", e));
+                false
+            }
+        }
+    }
+
+    async fn cancel_orders(&self, report: &mut KillSwitchReport) {
+        let mut symbols = BTreeSet::new();
+        match self.api_client.get_open_orders().await {
+            Ok(orders) => match orders.as_array() {
+                Some(orders) => {
+                    symbols.extend(
+                        orders
+                            .iter()
+                            .filter_map(|o| o["symbol"].as_str().map(|s| s.to_owned())),
+                    );
+                }
+                None => report
+                    .errors
+                    .push(format!("Unexpected open orders response: {}", orders)),
+            },
+            Err(e) => report.errors.push(format!("Get open orders failed: {}", e)),
+        }
+        for symbol in symbols {
+            match self.api_client.cancel_all_open_orders(&symbol).await {
+                Ok(response) if response["code"] == 200 => {
+                    info!("Cancelled open orders of {}", symbol);
+                    report.cancelled_symbols.push(symbol);
+                }
+                Ok(response) => report
+                    .errors
+                    .push(format!("Cancel {} orders failed: {}", symbol, response)),
+                Err(e) => report
+                    .errors
+                    .push(format!("Cancel {} orders failed: {}", symbol, e)),
+            }
+        }
+    }
+
+    async fn close_position(
+        &self,
+        position: &Position,
+        instruments: &HashMap<String, InstrumentInfo>,
+        report: &mut KillSwitchReport,
+    ) {
+        if position.position_side != "BOTH" {
+            report.errors.push(format!(
+                "Can't close {} {} position, hedge mode isn't supported",
+                position.symbol, position.position_side
+            ));
+            return;
+        }
+        let instrument_info = match instruments.get(&position.symbol) {
+            Some(instrument_info) => instrument_info,
+            None => {
+                report
+                    .errors
+                    .push(format!("Unknown symbol {}", position.symbol));
+                return;
+            }
+        };
+        let side = if position.position_amt > 0. {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        };
+        let mut order =
+            Order::market_order(position.symbol.clone(), side, position.position_amt.abs());
+        order.reduce_only = true;
+        let mut record = OrderRecord {
+            ts: Utc::now().timestamp_millis(),
+            symbol: order.symbol.clone(),
+            order: order.clone(),
+            tp_price: 0.,
+            sl_price: 0.,
+            response: None,
+            error: None,
+        };
+        match self.api_client.place_order(order, instrument_info).await {
+            Ok(response) => {
+                if let Some(code) = response.get("code") {
+                    warn!("Close {} rejected: {}", position.symbol, response);
+                    record.error = Some(format!("Binance error {}: {}", code, response["msg"]));
+                }
+                record.response = Some(response);
+            }
+            Err(e) => {
+                warn!("Close {} failed: {}", position.symbol, e);
+                record.error = Some(e.to_string());
+            }
+        }
+        report.orders.push(record);
+    }
+
+    async fn journal(&self, report: &KillSwitchReport) {
+        if let Some((mongo_client, mongo_config)) = &self.journal {
+            for record in report.orders.iter() {
+                if let Err(e) = mongo_client
+                    .insert_order_record(
+                        &mongo_config.database,
+                        &mongo_config.order_collection,
+                        record,
+                    )
+                    .await
+                {
+                    warn!("Journal order failed: {}", e);
+                }
+            }
+            let result = match bson::to_document(report) {
+                Ok(doc) => mongo_client
+                    .insert_documents(
+                        &mongo_config.database,
+                        &mongo_config.kill_switch_collection,
+                        vec![doc],
+                    )
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                warn!("Journal kill switch report failed: {}", e);
+            }
+        }
+    }
+}
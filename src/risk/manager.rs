@@ -0,0 +1,365 @@
+use log::warn;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::types::account::Account;
+use crate::types::config::RiskLimits;
+use crate::types::order::{Order, OrderSide};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Why the risk manager refused an order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskRejection {
+    SymbolNotAllowed {
+        symbol: String,
+    },
+    NoMarkPrice {
+        symbol: String,
+    },
+    PriceDeviation {
+        price: f64,
+        mark_price: f64,
+        limit: f64,
+    },
+    OrderNotional {
+        notional: f64,
+        limit: f64,
+    },
+    SymbolNotional {
+        symbol: String,
+        notional: f64,
+        limit: f64,
+    },
+    Leverage {
+        leverage: f64,
+        limit: f64,
+    },
+    OpenPositions {
+        open: usize,
+        limit: usize,
+    },
+    DailyLoss {
+        loss: f64,
+        limit: f64,
+    },
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskRejection::SymbolNotAllowed { symbol } => {
+                write!(f, "Symbol {} is not allowed", symbol)
+            }
+            RiskRejection::NoMarkPrice { symbol } => {
+                write!(f, "No mark price for {} to value the order", symbol)
+            }
+            RiskRejection::PriceDeviation {
+                price,
+                mark_price,
+                limit,
+            } => write!(
+                f,
+                "Price {} deviates more than {} from mark price {}",
+                price, limit, mark_price
+            ),
+            RiskRejection::OrderNotional { notional, limit } => {
+                write!(f, "Order notional {} exceeds {}", notional, limit)
+            }
+            RiskRejection::SymbolNotional {
+                symbol,
+                notional,
+                limit,
+            } => write!(
+                f,
+                "Position notional {} on {} would exceed {}",
+                notional, symbol, limit
+            ),
+            RiskRejection::Leverage { leverage, limit } => {
+                write!(f, "Leverage {} would exceed {}", leverage, limit)
+            }
+            RiskRejection::OpenPositions { open, limit } => {
+                write!(f, "{} open positions would exceed {}", open, limit)
+            }
+            RiskRejection::DailyLoss { loss, limit } => {
+                write!(f, "Daily loss {} reached the limit {}", loss, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RiskRejection {}
+
+/// Pre-trade checks of every order against the `RiskLimits`. Reduce only
+/// orders lower the exposure and only go through the price check, so
+/// positions can always be closed.
+///
+/// Mark prices, e.g. kline closes, are the reference the price check
+/// compares against. Market orders are checked with the last traded price.
+#[derive(Debug, Clone)]
+pub struct RiskManager {
+    limits: RiskLimits,
+    mark_prices: HashMap<String, f64>,
+    last_prices: HashMap<String, f64>,
+    day_start: Option<(i64, f64)>, // UTC day and the equity at its start
+}
+
+impl RiskManager {
+    pub fn new(limits: RiskLimits) -> RiskManager {
+        RiskManager {
+            limits,
+            mark_prices: HashMap::new(),
+            last_prices: HashMap::new(),
+            day_start: None,
+        }
+    }
+
+    pub fn limits(&self) -> &RiskLimits {
+        &self.limits
+    }
+
+    pub fn update_mark_price(&mut self, symbol: &str, price: f64) {
+        self.mark_prices.insert(symbol.to_owned(), price);
+    }
+
+    pub fn mark_price(&self, symbol: &str) -> Option<f64> {
+        self.mark_prices.get(symbol).copied()
+    }
+
+    /// Price a market order would trade at, e.g. from the ticker.
+    pub fn update_last_price(&mut self, symbol: &str, price: f64) {
+        self.last_prices.insert(symbol.to_owned(), price);
+    }
+
+    pub fn last_price(&self, symbol: &str) -> Option<f64> {
+        self.last_prices.get(symbol).copied()
+    }
+
+    /// Start a new daily loss baseline when the UTC day rolls over. Call it
+    /// regularly, e.g. on every reconcile, so the baseline is the equity at
+    /// the start of the day rather than at its first order. Returns the
+    /// baseline.
+    pub fn update_equity(&mut self, equity: f64, ts: i64) -> f64 {
+        let day = ts.div_euclid(DAY_MS);
+        match self.day_start {
+            Some((start_day, start_equity)) if start_day == day => start_equity,
+            _ => {
+                self.day_start = Some((day, equity));
+                equity
+            }
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        order: &Order,
+        account: &Account,
+        ts: i64,
+    ) -> Result<(), RiskRejection> {
+        let result = self.check_order(order, account, ts);
+        if let Err(rejection) = &result {
+            warn!("Risk rejected {:?}: {}", order, rejection);
+        }
+        result
+    }
+
+    fn check_order(
+        &mut self,
+        order: &Order,
+        account: &Account,
+        ts: i64,
+    ) -> Result<(), RiskRejection> {
+        // Exits must go through even when the price runs away
+        if order.reduce_only {
+            return Ok(());
+        }
+        let mark_price = self.mark_price(&order.symbol);
+        let order_price = order.price.or_else(|| self.last_price(&order.symbol));
+        if let (Some(limit), Some(price), Some(mark_price)) =
+            (self.limits.max_price_deviation, order_price, mark_price)
+        {
+            if (price - mark_price).abs() > limit * mark_price {
+                return Err(RiskRejection::PriceDeviation {
+                    price,
+                    mark_price,
+                    limit,
+                });
+            }
+        }
+
+        if let Some(allowed) = &self.limits.allowed_symbols {
+            if !allowed.contains(&order.symbol) {
+                return Err(RiskRejection::SymbolNotAllowed {
+                    symbol: order.symbol.clone(),
+                });
+            }
+        }
+
+        let equity = account.equity();
+        if let Some(limit) = self.limits.max_daily_loss {
+            let start_equity = self.update_equity(equity, ts);
+            if start_equity > 0. {
+                let loss = (start_equity - equity) / start_equity;
+                if loss >= limit {
+                    return Err(RiskRejection::DailyLoss { loss, limit });
+                }
+            }
+        }
+
+        let needs_price = self.limits.max_order_notional.is_some()
+            || self.limits.max_symbol_notional.is_some()
+            || self.limits.max_leverage.is_some();
+        let price = match order_price.or(mark_price) {
+            Some(price) => price,
+            None if needs_price => {
+                return Err(RiskRejection::NoMarkPrice {
+                    symbol: order.symbol.clone(),
+                })
+            }
+            None => 0.,
+        };
+
+        let notional = order.size * price;
+        if let Some(limit) = self.limits.max_order_notional {
+            if notional > limit {
+                return Err(RiskRejection::OrderNotional { notional, limit });
+            }
+        }
+
+        // Signed position of every symbol once the order fills
+        let mut positions: HashMap<&str, (f64, f64)> = HashMap::new();
+        for position in account.positions.iter() {
+            let entry = positions
+                .entry(position.symbol.as_str())
+                .or_insert((0., position.entry_price));
+            entry.0 += position.position_amt;
+        }
+        let quantity = match order.order_side {
+            OrderSide::Buy => order.size,
+            OrderSide::Sell => -order.size,
+        };
+        positions
+            .entry(order.symbol.as_str())
+            .or_insert((0., price))
+            .0 += quantity;
+
+        if let Some(limit) = self.limits.max_symbol_notional {
+            let notional = positions[order.symbol.as_str()].0.abs() * price;
+            if notional > limit {
+                return Err(RiskRejection::SymbolNotional {
+                    symbol: order.symbol.clone(),
+                    notional,
+                    limit,
+                });
+            }
+        }
+        if let Some(limit) = self.limits.max_open_positions {
+            let open = positions.values().filter(|(q, _)| *q != 0.).count();
+            if open > limit {
+                return Err(RiskRejection::OpenPositions { open, limit });
+            }
+        }
+        if let Some(limit) = self.limits.max_leverage {
+            let exposure: f64 = positions
+                .iter()
+                .map(|(symbol, (quantity, entry_price))| {
+                    let price = if *symbol == order.symbol {
+                        price
+                    } else {
+                        self.mark_price(symbol).unwrap_or(*entry_price)
+                    };
+                    quantity.abs() * price
+                })
+                .sum();
+            let leverage = if equity > 0. {
+                exposure / equity
+            } else {
+                f64::INFINITY
+            };
+            if leverage > limit {
+                return Err(RiskRejection::Leverage { leverage, limit });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::account::Asset;
+
+    fn account(balance: f64) -> Account {
+        Account {
+            assets: vec![Asset {
+                asset: "USDT".to_owned(),
+                wallet_balance: balance,
+                available_balance: balance,
+                update_timestamp: 0,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn buy() -> Order {
+        Order::market_order("BTCUSDT".to_owned(), OrderSide::Buy, 1.)
+    }
+
+    #[test]
+    fn market_order_checked_against_last_price() {
+        let mut manager = RiskManager::new(RiskLimits {
+            max_price_deviation: Some(0.05),
+            ..Default::default()
+        });
+        manager.update_mark_price("BTCUSDT", 100.);
+        assert!(manager.check(&buy(), &account(1000.), 0).is_ok());
+
+        manager.update_last_price("BTCUSDT", 110.);
+        assert_eq!(
+            manager.check(&buy(), &account(1000.), 0),
+            Err(RiskRejection::PriceDeviation {
+                price: 110.,
+                mark_price: 100.,
+                limit: 0.05
+            })
+        );
+        manager.update_last_price("BTCUSDT", 104.);
+        assert!(manager.check(&buy(), &account(1000.), 0).is_ok());
+    }
+
+    #[test]
+    fn reduce_only_orders_pass_out_of_band() {
+        let mut manager = RiskManager::new(RiskLimits {
+            max_price_deviation: Some(0.05),
+            ..Default::default()
+        });
+        manager.update_mark_price("BTCUSDT", 100.);
+        manager.update_last_price("BTCUSDT", 120.);
+        let mut exit = Order::market_order("BTCUSDT".to_owned(), OrderSide::Sell, 1.);
+        assert!(manager.check(&exit, &account(1000.), 0).is_err());
+        exit.reduce_only = true;
+        assert!(manager.check(&exit, &account(1000.), 0).is_ok());
+    }
+
+    #[test]
+    fn daily_loss_counts_from_day_start() {
+        let mut manager = RiskManager::new(RiskLimits {
+            max_daily_loss: Some(0.1),
+            ..Default::default()
+        });
+        // Loss before the first order of the day still counts
+        manager.update_equity(1000., DAY_MS + 1);
+        assert_eq!(
+            manager.check(&buy(), &account(850.), DAY_MS + 60_000),
+            Err(RiskRejection::DailyLoss {
+                loss: 0.15,
+                limit: 0.1
+            })
+        );
+        // New baseline on the next day
+        manager.update_equity(850., 2 * DAY_MS);
+        assert!(manager
+            .check(&buy(), &account(850.), 2 * DAY_MS + 1)
+            .is_ok());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod kill_switch;
+pub mod manager;
+pub mod sizing;
@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::types::account::Account;
+use crate::types::instrument::InstrumentInfo;
+
+// Absorbs float error before rounding down to the step size
+const STEP_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SizingMethod {
+    FixedNotional {
+        notional: f64,
+    },
+    // Lose `risk_fraction` of the equity if the stop loss is hit
+    FixedFractional {
+        risk_fraction: f64,
+    },
+    // Same with the stop `atr_multiple` ATRs away from the price
+    Volatility {
+        risk_fraction: f64,
+        atr_multiple: f64,
+    },
+    // Notional of `fraction` times the Kelly fraction of the equity
+    Kelly {
+        win_rate: f64,
+        win_loss_ratio: f64, // average win over average loss
+        fraction: f64,
+    },
+}
+
+/// Market data a sizing method may need, the stop loss and the ATR are only
+/// required by the methods using them.
+#[derive(Debug, Clone, Default)]
+pub struct SizingInput {
+    pub price: f64,
+    pub sl_price: Option<f64>,
+    pub atr: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SizingError {
+    InvalidPrice(f64),
+    MissingStopLoss,
+    MissingAtr,
+    NoEdge { kelly_fraction: f64 },
+    BelowMinQty { size: f64, min_qty: f64 },
+    BelowMinNotional { notional: f64, min_notional: f64 },
+}
+
+impl fmt::Display for SizingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizingError::InvalidPrice(price) => write!(f, "Invalid price {}", price),
+            SizingError::MissingStopLoss => {
+                write!(f, "Sizing needs a stop loss away from the price")
+            }
+            SizingError::MissingAtr => write!(f, "Sizing needs a positive ATR"),
+            SizingError::NoEdge { kelly_fraction } => {
+                write!(
+                    f,
+                    "Kelly fraction {} leaves nothing to trade",
+                    kelly_fraction
+                )
+            }
+            SizingError::BelowMinQty { size, min_qty } => {
+                write!(f, "Size {} below the min quantity {}", size, min_qty)
+            }
+            SizingError::BelowMinNotional {
+                notional,
+                min_notional,
+            } => write!(
+                f,
+                "Notional {} below the min notional {}",
+                notional, min_notional
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SizingError {}
+
+/// Turns a sizing method into an order size the exchange accepts: capped by
+/// the margin available at `leverage` and rounded down to the step size.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionSizer {
+    pub method: SizingMethod,
+    pub leverage: f64,
+}
+
+impl PositionSizer {
+    pub fn new(method: SizingMethod, leverage: f64) -> PositionSizer {
+        PositionSizer { method, leverage }
+    }
+
+    /// Size from the account's equity and available USDT balance.
+    pub fn size(
+        &self,
+        account: &Account,
+        instrument_info: &InstrumentInfo,
+        input: &SizingInput,
+    ) -> Result<f64, SizingError> {
+        self.size_for_balance(
+            account.equity(),
+            account.get_usd_balance(),
+            instrument_info,
+            input,
+        )
+    }
+
+    pub fn size_for_balance(
+        &self,
+        equity: f64,
+        available_balance: f64,
+        instrument_info: &InstrumentInfo,
+        input: &SizingInput,
+    ) -> Result<f64, SizingError> {
+        let price = input.price;
+        if price <= 0. || !price.is_finite() {
+            return Err(SizingError::InvalidPrice(price));
+        }
+        let size = match &self.method {
+            SizingMethod::FixedNotional { notional } => notional / price,
+            SizingMethod::FixedFractional { risk_fraction } => {
+                let stop_distance = input
+                    .sl_price
+                    .map(|sl_price| (price - sl_price).abs())
+                    .filter(|distance| *distance > 0.)
+                    .ok_or(SizingError::MissingStopLoss)?;
+                equity * risk_fraction / stop_distance
+            }
+            SizingMethod::Volatility {
+                risk_fraction,
+                atr_multiple,
+            } => {
+                let atr = input
+                    .atr
+                    .filter(|atr| *atr > 0.)
+                    .ok_or(SizingError::MissingAtr)?;
+                equity * risk_fraction / (atr * atr_multiple)
+            }
+            SizingMethod::Kelly {
+                win_rate,
+                win_loss_ratio,
+                fraction,
+            } => {
+                let kelly_fraction = kelly_fraction(*win_rate, *win_loss_ratio);
+                if kelly_fraction <= 0. {
+                    return Err(SizingError::NoEdge { kelly_fraction });
+                }
+                equity * kelly_fraction * fraction / price
+            }
+        };
+
+        let max_size = available_balance.max(0.) * self.leverage / price;
+        let size = round_down(size.min(max_size), instrument_info.step_size());
+        if size < instrument_info.min_qty || size <= 0. {
+            return Err(SizingError::BelowMinQty {
+                size,
+                min_qty: instrument_info.min_qty,
+            });
+        }
+        if size * price < instrument_info.min_notional {
+            return Err(SizingError::BelowMinNotional {
+                notional: size * price,
+                min_notional: instrument_info.min_notional,
+            });
+        }
+        Ok(size)
+    }
+}
+
+/// Fraction of the bankroll to bet, `p - (1 - p) / b`.
+pub fn kelly_fraction(win_rate: f64, win_loss_ratio: f64) -> f64 {
+    if win_loss_ratio <= 0. {
+        return 0.;
+    }
+    win_rate - (1. - win_rate) / win_loss_ratio
+}
+
+pub fn round_down(size: f64, step_size: f64) -> f64 {
+    if step_size <= 0. {
+        return size;
+    }
+    (size / step_size + STEP_EPSILON).floor() * step_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::account::{Asset, Position};
+
+    const PRICE: f64 = 30000.;
+
+    fn btcusdt() -> InstrumentInfo {
+        InstrumentInfo {
+            symbol: "BTCUSDT".to_owned(),
+            tick_size: "0.10".to_owned(),
+            lot_size: "0.001".to_owned(),
+            min_qty: 0.001,
+            min_notional: 5.,
+        }
+    }
+
+    fn size(method: SizingMethod, input: &SizingInput) -> Result<f64, SizingError> {
+        PositionSizer::new(method, 1.).size_for_balance(10000., 10000., &btcusdt(), input)
+    }
+
+    fn at(price: f64) -> SizingInput {
+        SizingInput {
+            price,
+            ..Default::default()
+        }
+    }
+
+    fn assert_size(result: Result<f64, SizingError>, expected: f64) {
+        let size = result.unwrap();
+        assert!((size - expected).abs() < 1e-12, "{} != {}", size, expected);
+    }
+
+    #[test]
+    fn fixed_notional_rounds_down_to_the_step() {
+        let method = SizingMethod::FixedNotional { notional: 1000. };
+        // 0.0333.. BTC
+        assert_size(size(method.clone(), &at(PRICE)), 0.033);
+        assert_eq!(size(method, &at(0.)), Err(SizingError::InvalidPrice(0.)));
+    }
+
+    #[test]
+    fn fixed_fractional_risks_the_stop_distance() {
+        let method = SizingMethod::FixedFractional {
+            risk_fraction: 0.01,
+        };
+        let mut input = at(PRICE);
+        assert_eq!(
+            size(method.clone(), &input),
+            Err(SizingError::MissingStopLoss)
+        );
+        // 100 USDT over a 500 USDT stop, for longs and shorts alike
+        input.sl_price = Some(PRICE - 500.);
+        assert_size(size(method.clone(), &input), 0.2);
+        input.sl_price = Some(PRICE + 500.);
+        assert_size(size(method.clone(), &input), 0.2);
+        input.sl_price = Some(PRICE);
+        assert_eq!(size(method, &input), Err(SizingError::MissingStopLoss));
+    }
+
+    #[test]
+    fn volatility_puts_the_stop_atr_multiples_away() {
+        let method = SizingMethod::Volatility {
+            risk_fraction: 0.01,
+            atr_multiple: 2.,
+        };
+        let mut input = at(PRICE);
+        assert_eq!(size(method.clone(), &input), Err(SizingError::MissingAtr));
+        input.atr = Some(250.);
+        assert_size(size(method, &input), 0.2);
+    }
+
+    #[test]
+    fn kelly_bets_a_fraction_of_the_equity() {
+        assert!((kelly_fraction(0.6, 2.) - 0.4).abs() < 1e-12);
+        let method = SizingMethod::Kelly {
+            win_rate: 0.6,
+            win_loss_ratio: 2.,
+            fraction: 0.5,
+        };
+        // 2000 USDT notional, 0.0666.. BTC
+        assert_size(size(method, &at(PRICE)), 0.066);
+        let no_edge = SizingMethod::Kelly {
+            win_rate: 0.3,
+            win_loss_ratio: 1.,
+            fraction: 0.5,
+        };
+        assert!(matches!(
+            size(no_edge, &at(PRICE)),
+            Err(SizingError::NoEdge { .. })
+        ));
+    }
+
+    #[test]
+    fn size_is_capped_by_the_margin() {
+        let account = Account {
+            assets: vec![Asset {
+                asset: "USDT".to_owned(),
+                wallet_balance: 2000.,
+                available_balance: 1000.,
+                update_timestamp: 0,
+            }],
+            positions: vec![Position {
+                symbol: "ETHUSDT".to_owned(),
+                unrealized_profit: 500.,
+                ..Default::default()
+            }],
+        };
+        let sizer = PositionSizer::new(SizingMethod::FixedFractional { risk_fraction: 0.1 }, 5.);
+        let input = SizingInput {
+            price: PRICE,
+            sl_price: Some(PRICE - 1000.),
+            atr: None,
+        };
+        // Risking 10% of 2500 equity wants 0.25 BTC, 1000 USDT at 5x buys 0.1666..
+        assert_size(sizer.size(&account, &btcusdt(), &input), 0.166);
+        let sizer = PositionSizer::new(sizer.method, 10.);
+        assert_size(sizer.size(&account, &btcusdt(), &input), 0.25);
+    }
+
+    #[test]
+    fn sizes_below_the_instrument_minimums_are_rejected() {
+        let dust = SizingMethod::FixedNotional { notional: 20. };
+        assert_eq!(
+            size(dust, &at(PRICE)),
+            Err(SizingError::BelowMinQty {
+                size: 0.,
+                min_qty: 0.001
+            })
+        );
+        let mut fine_steps = btcusdt();
+        fine_steps.lot_size = "0.0001".to_owned();
+        fine_steps.min_qty = 0.0001;
+        let small = PositionSizer::new(SizingMethod::FixedNotional { notional: 4. }, 1.);
+        let result = small.size_for_balance(10000., 10000., &fine_steps, &at(PRICE));
+        match result {
+            Err(SizingError::BelowMinNotional {
+                notional,
+                min_notional,
+            }) => {
+                assert!((notional - 3.).abs() < 1e-9);
+                assert_eq!(min_notional, 5.);
+            }
+            other => panic!("Expected BelowMinNotional, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_down_absorbs_float_error() {
+        // 0.3 / 0.1 is 2.9999999999999996
+        assert!((round_down(0.3, 0.1) - 0.3).abs() < 1e-12);
+        assert!((round_down(0.0339, 0.001) - 0.033).abs() < 1e-12);
+        assert_eq!(round_down(0.0339, 0.), 0.0339);
+    }
+}
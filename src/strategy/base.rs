@@ -0,0 +1,35 @@
+use crate::strategy::context::StrategyContext;
+use crate::types::kline::Kline;
+use crate::types::order::Fill;
+
+/// Lifecycle hooks shared by the backtester and the live runner. Orders placed
+/// on the context are executed by the driver once the hook returns.
+pub trait Strategy {
+    fn on_start(&mut self, _ctx: &mut StrategyContext) {}
+    fn on_kline(&mut self, kline: &Kline, ctx: &mut StrategyContext);
+    fn on_fill(&mut self, _fill: &Fill, _ctx: &mut StrategyContext) {}
+    fn on_timer(&mut self, _ts: i64, _ctx: &mut StrategyContext) {}
+    fn on_stop(&mut self, _ctx: &mut StrategyContext) {}
+}
+
+impl<S: Strategy + ?Sized> Strategy for Box<S> {
+    fn on_start(&mut self, ctx: &mut StrategyContext) {
+        (**self).on_start(ctx)
+    }
+
+    fn on_kline(&mut self, kline: &Kline, ctx: &mut StrategyContext) {
+        (**self).on_kline(kline, ctx)
+    }
+
+    fn on_fill(&mut self, fill: &Fill, ctx: &mut StrategyContext) {
+        (**self).on_fill(fill, ctx)
+    }
+
+    fn on_timer(&mut self, ts: i64, ctx: &mut StrategyContext) {
+        (**self).on_timer(ts, ctx)
+    }
+
+    fn on_stop(&mut self, ctx: &mut StrategyContext) {
+        (**self).on_stop(ctx)
+    }
+}
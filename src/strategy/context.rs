@@ -0,0 +1,48 @@
+use crate::types::account::Account;
+use crate::types::order::Order;
+use crate::types::trade::Trade;
+
+#[derive(Debug, Clone)]
+pub struct OrderRequest {
+    pub order: Order,
+    pub tp_price: f64, // 0. means no take profit
+    pub sl_price: f64, // 0. means no stop loss
+}
+
+/// State handed to every `Strategy` hook. The driver (backtester, paper or
+/// live runner) refreshes `account` and `open_trades` before each call and
+/// executes the submitted orders after it returns.
+#[derive(Debug, Default)]
+pub struct StrategyContext {
+    pub ts: i64,
+    pub account: Account,
+    pub open_trades: Vec<Trade>,
+    orders: Vec<OrderRequest>,
+}
+
+impl StrategyContext {
+    pub fn new(ts: i64, account: Account, open_trades: Vec<Trade>) -> StrategyContext {
+        StrategyContext {
+            ts,
+            account,
+            open_trades,
+            orders: Vec::new(),
+        }
+    }
+
+    pub fn place_order(&mut self, order: Order) {
+        self.place_order_with_exits(order, 0., 0.);
+    }
+
+    pub fn place_order_with_exits(&mut self, order: Order, tp_price: f64, sl_price: f64) {
+        self.orders.push(OrderRequest {
+            order,
+            tp_price,
+            sl_price,
+        });
+    }
+
+    pub fn take_orders(&mut self) -> Vec<OrderRequest> {
+        std::mem::take(&mut self.orders)
+    }
+}
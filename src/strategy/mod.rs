@@ -0,0 +1,4 @@
+pub mod base;
+pub mod context;
+pub mod registry;
+pub mod sma_cross;
@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::strategy::base::Strategy;
+use crate::strategy::sma_cross::SmaCross;
+
+pub const STRATEGY_NAMES: [&str; 1] = ["sma_cross"];
+
+/// Build the strategy named by the `name` key of the config's "strategy"
+/// section, the other keys are its parameters.
+pub fn build_strategy(symbol: &str, config: &Value) -> Result<Box<dyn Strategy>> {
+    let name = config["name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Config \"strategy.name\" is required"))?;
+    match name {
+        "sma_cross" => Ok(Box::new(SmaCross::from_value(symbol, config)?)),
+        _ => Err(anyhow!(
+            "Unknown strategy \"{}\", expected one of {:?}",
+            name,
+            STRATEGY_NAMES
+        )),
+    }
+}
+
+/// Strategy config with the keys of a hypertune parameter set replaced.
+pub fn with_params(config: &Value, params: &Value) -> Value {
+    let mut config = config.clone();
+    if let (Some(config), Some(params)) = (config.as_object_mut(), params.as_object()) {
+        for (key, value) in params.iter() {
+            config.insert(key.clone(), value.clone());
+        }
+    }
+    config
+}
@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::indicators::moving::Sma;
+use crate::strategy::base::Strategy;
+use crate::strategy::context::StrategyContext;
+use crate::types::kline::Kline;
+use crate::types::order::{Order, OrderSide};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmaCrossParams {
+    #[serde(default = "default_fast")]
+    pub fast: usize,
+    #[serde(default = "default_slow")]
+    pub slow: usize,
+    pub size: f64, // position held long or short
+}
+
+fn default_fast() -> usize {
+    10
+}
+
+fn default_slow() -> usize {
+    30
+}
+
+/// Always in the market: long while the fast SMA of the close is above the
+/// slow one, short below. Flips the position on every cross.
+#[derive(Debug, Clone)]
+pub struct SmaCross {
+    symbol: String,
+    params: SmaCrossParams,
+    fast: Sma,
+    slow: Sma,
+}
+
+impl SmaCross {
+    pub fn new(symbol: &str, params: SmaCrossParams) -> Result<SmaCross> {
+        if params.fast == 0 || params.fast >= params.slow {
+            return Err(anyhow!("Strategy \"fast\" must be below \"slow\""));
+        }
+        if params.size <= 0. {
+            return Err(anyhow!("Strategy \"size\" must be positive"));
+        }
+        Ok(SmaCross {
+            symbol: symbol.to_owned(),
+            fast: Sma::new(params.fast),
+            slow: Sma::new(params.slow),
+            params,
+        })
+    }
+
+    pub fn from_value(symbol: &str, value: &Value) -> Result<SmaCross> {
+        SmaCross::new(symbol, serde_json::from_value(value.clone())?)
+    }
+}
+
+impl Strategy for SmaCross {
+    fn on_kline(&mut self, kline: &Kline, ctx: &mut StrategyContext) {
+        let (fast, slow) = match (self.fast.next(kline.close), self.slow.next(kline.close)) {
+            (Some(fast), Some(slow)) if fast != slow => (fast, slow),
+            _ => return,
+        };
+        let target = if fast > slow {
+            self.params.size
+        } else {
+            -self.params.size
+        };
+        let position: f64 = ctx
+            .open_trades
+            .iter()
+            .filter(|t| t.symbol == self.symbol)
+            .map(|t| t.position * t.entry_side.value())
+            .sum();
+        let quantity = target - position;
+        if quantity.abs() <= f64::EPSILON * self.params.size {
+            return;
+        }
+        let side = if quantity > 0. {
+            OrderSide::Buy
+        } else {
+            OrderSide::Sell
+        };
+        ctx.place_order(Order::market_order(
+            self.symbol.clone(),
+            side,
+            quantity.abs(),
+        ));
+    }
+}
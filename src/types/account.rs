@@ -1,4 +1,6 @@
-#[derive(Default, Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub assets: Vec<Asset>,
     pub positions: Vec<Position>,
@@ -13,9 +15,20 @@ impl Account {
         }
         0.
     }
+
+    /// USDT wallet balance plus the unrealized PnL of the positions.
+    pub fn equity(&self) -> f64 {
+        let wallet_balance = self
+            .assets
+            .iter()
+            .find(|asset| asset.asset == "USDT")
+            .map_or(0., |asset| asset.wallet_balance);
+        let unrealized: f64 = self.positions.iter().map(|p| p.unrealized_profit).sum();
+        wallet_balance + unrealized
+    }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Position {
     pub symbol: String,
     pub unrealized_profit: f64,
@@ -25,10 +38,17 @@ pub struct Position {
     pub position_amt: f64,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Asset {
     pub asset: String,
     pub wallet_balance: f64,
     pub available_balance: f64,
     pub update_timestamp: i64,
 }
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub ts: i64,
+    #[serde(flatten)]
+    pub account: Account,
+}
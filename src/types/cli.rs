@@ -8,6 +8,9 @@ pub struct Cli {
     pub config_path: PathBuf,
     #[arg(short = 'm')]
     pub mode: Mode,
+    /// Override a config value, e.g. `-s live.poll_interval_ms=500`
+    #[arg(short = 's', long = "set")]
+    pub overrides: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -15,6 +18,8 @@ pub enum Mode {
     Backtest,
     Hypertune,
     Live,
+    Sync,
+    Kill, // cancel all orders and flatten all positions
 }
 
 impl FromStr for Mode {
@@ -25,9 +30,13 @@ impl FromStr for Mode {
             "backtest" => Ok(Mode::Backtest),
             "hypertune" => Ok(Mode::Hypertune),
             "live" => Ok(Mode::Live),
+            "sync" => Ok(Mode::Sync),
+            "kill" => Ok(Mode::Kill),
             "b" => Ok(Mode::Backtest),
             "h" => Ok(Mode::Hypertune),
             "l" => Ok(Mode::Live),
+            "s" => Ok(Mode::Sync),
+            "k" => Ok(Mode::Kill),
             _ => Err(format!("Invalid mode: {}", s)),
         }
     }
@@ -0,0 +1,558 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::backtest::engine::BacktestConfig;
+use crate::hypertune::config::HypertuneConfig;
+use crate::live::config::LiveConfig;
+use crate::types::interval::KlineInterval;
+use crate::types::schedule::Schedule;
+use crate::types::timer::FixedUpdate;
+
+/// A value given either inline or as the name of an environment variable,
+/// e.g. `api_key = { env = "BINANCE_API_KEY" }`. Inline values are redacted
+/// from the `Debug` output.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Secret {
+    Plain(String),
+    Env { env: String },
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            Secret::Plain(value) => Ok(value.clone()),
+            Secret::Env { env } => std::env::var(env)
+                .with_context(|| format!("Environment variable \"{}\" is not set", env)),
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Secret::Plain(value) if value.is_empty() => write!(f, "Plain(\"\")"),
+            Secret::Plain(_) => write!(f, "Plain(<redacted>)"),
+            Secret::Env { env } => f.debug_struct("Env").field("env", env).finish(),
+        }
+    }
+}
+
+impl Default for Secret {
+    fn default() -> Self {
+        Secret::Plain("".to_owned())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExchangeCredentials {
+    #[serde(default)]
+    pub api_key: Secret,
+    #[serde(default)]
+    pub secret_key: Secret,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MongoConfig {
+    pub connection_string: Secret,
+    pub database: String,
+    #[serde(default = "default_kline_collection")]
+    pub kline_collection: String, // "{symbol}" and "{interval}" are substituted
+    #[serde(default = "default_trade_collection")]
+    pub trade_collection: String,
+    #[serde(default = "default_order_collection")]
+    pub order_collection: String,
+    #[serde(default = "default_account_collection")]
+    pub account_collection: String,
+    #[serde(default = "default_kill_switch_collection")]
+    pub kill_switch_collection: String,
+    #[serde(default = "default_funding_collection")]
+    pub funding_collection: String, // "{symbol}" is substituted
+}
+
+fn default_kline_collection() -> String {
+    "{symbol}_{interval}".to_owned()
+}
+
+fn default_trade_collection() -> String {
+    "trades".to_owned()
+}
+
+fn default_order_collection() -> String {
+    "orders".to_owned()
+}
+
+fn default_account_collection() -> String {
+    "account_snapshots".to_owned()
+}
+
+fn default_kill_switch_collection() -> String {
+    "kill_switch".to_owned()
+}
+
+fn default_funding_collection() -> String {
+    "{symbol}_funding".to_owned()
+}
+
+impl MongoConfig {
+    pub fn kline_collection(&self, symbol: &str, interval: KlineInterval) -> String {
+        self.kline_collection
+            .replace("{symbol}", symbol)
+            .replace("{interval}", interval.as_str())
+    }
+
+    pub fn funding_collection(&self, symbol: &str) -> String {
+        self.funding_collection.replace("{symbol}", symbol)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RiskLimits {
+    pub max_order_notional: Option<f64>,
+    pub max_symbol_notional: Option<f64>,
+    pub max_leverage: Option<f64>,
+    pub max_open_positions: Option<usize>,
+    pub max_daily_loss: Option<f64>, // fraction of the balance at the start of the day
+    pub allowed_symbols: Option<Vec<String>>,
+    pub max_price_deviation: Option<f64>, // of the order or last price, fraction of the mark price
+    #[serde(default)]
+    pub kill_on_daily_loss: bool, // flatten everything once the daily loss limit is hit
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BacktestSection {
+    #[serde(flatten)]
+    pub config: BacktestConfig,
+    pub csv_path: Option<PathBuf>, // read klines from csv instead of MongoDB
+    pub funding_csv_path: Option<PathBuf>, // funding rates for `FundingModel::History`
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveSection {
+    #[serde(default = "default_history_limit")]
+    pub history_limit: u64,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    #[serde(default = "default_live_fee_rate")]
+    pub fee_rate: f64, // taker rate, estimates fees the exchange didn't report
+}
+
+fn default_history_limit() -> u64 {
+    500
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_live_fee_rate() -> f64 {
+    0.0005
+}
+
+impl Default for LiveSection {
+    fn default() -> Self {
+        LiveSection {
+            history_limit: default_history_limit(),
+            poll_interval_ms: default_poll_interval_ms(),
+            fee_rate: default_live_fee_rate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncSection {
+    #[serde(default)]
+    pub intervals: Vec<KlineInterval>, // defaults to the top level interval
+    #[serde(default)]
+    pub start_ts: i64, // where to start if a collection is empty
+    #[serde(alias = "fixed_update")]
+    pub schedule: Option<Schedule>, // keep syncing on this schedule
+    #[serde(default)]
+    pub offset_ms: i64, // delay after each timer boundary so the last kline is final
+    #[serde(default)]
+    pub funding: bool, // sync the funding rate history of the symbols as well
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub exchange: ExchangeCredentials,
+    pub symbols: Vec<String>,
+    pub interval: KlineInterval,
+    pub fixed_update: Option<FixedUpdate>,
+    pub mongo: Option<MongoConfig>,
+    #[serde(default)]
+    pub strategy: Value,
+    #[serde(default)]
+    pub risk: RiskLimits,
+    #[serde(default)]
+    pub backtest: BacktestSection,
+    pub hypertune: Option<HypertuneConfig>,
+    #[serde(default)]
+    pub live: LiveSection,
+    #[serde(default)]
+    pub sync: SyncSection,
+}
+
+impl Config {
+    /// Load a toml, json or yaml file chosen by its extension. Overrides are
+    /// `dotted.key=value` pairs applied before deserializing, values that
+    /// aren't valid json are taken as strings.
+    pub fn from_path(path: &Path, overrides: &[String]) -> Result<Config> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read config file {:?}", path))?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let mut value: Value = match extension {
+            "toml" => toml::from_str(&content)?,
+            "json" => serde_json::from_str(&content)?,
+            "yaml" | "yml" => serde_yaml::from_str(&content)?,
+            _ => return Err(anyhow!("Unsupported config format: {:?}", path)),
+        };
+        for arg in overrides.iter() {
+            apply_override(&mut value, arg)?;
+        }
+        let config: Config = serde_json::from_value(value)
+            .with_context(|| format!("Invalid config file {:?}", path))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.symbols.is_empty() {
+            return Err(anyhow!("Config \"symbols\" must not be empty"));
+        }
+        let exchange = &self.backtest.config.exchange;
+        if exchange.leverage <= 0. {
+            return Err(anyhow!(
+                "Config \"backtest.exchange.leverage\" must be positive"
+            ));
+        }
+        if exchange.initial_balance <= 0. {
+            return Err(anyhow!(
+                "Config \"backtest.exchange.initial_balance\" must be positive"
+            ));
+        }
+        let fixed_updates = [
+            ("fixed_update", self.fixed_update.as_ref()),
+            (
+                "backtest.fixed_update",
+                self.backtest.config.fixed_update.as_ref(),
+            ),
+            (
+                "sync.schedule",
+                match &self.sync.schedule {
+                    Some(Schedule::Fixed(fixed_update)) => Some(fixed_update),
+                    _ => None,
+                },
+            ),
+        ];
+        for (key, fixed_update) in fixed_updates {
+            if let Some(fixed_update) = fixed_update {
+                if !fixed_update.is_valid() {
+                    return Err(anyhow!("Config \"{}\" must be positive", key));
+                }
+            }
+        }
+        if let Some(hypertune) = &self.hypertune {
+            hypertune
+                .validate()
+                .context("Invalid config \"hypertune\" section")?;
+        }
+        if let Some(max_leverage) = self.risk.max_leverage {
+            if max_leverage <= 0. {
+                return Err(anyhow!("Config \"risk.max_leverage\" must be positive"));
+            }
+        }
+        if let Some(max_price_deviation) = self.risk.max_price_deviation {
+            if max_price_deviation <= 0. {
+                return Err(anyhow!(
+                    "Config \"risk.max_price_deviation\" must be positive"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The backtest section, inheriting the top level `fixed_update` if it
+    /// doesn't set its own.
+    pub fn backtest_config(&self) -> BacktestConfig {
+        let mut backtest = self.backtest.config.clone();
+        if backtest.fixed_update.is_none() {
+            backtest.fixed_update = self.fixed_update.clone();
+        }
+        backtest
+    }
+
+    /// Backtests replay the klines of exactly one symbol.
+    pub fn backtest_symbol(&self) -> Result<&str> {
+        match self.symbols.as_slice() {
+            [symbol] => Ok(symbol),
+            _ => Err(anyhow!("Backtests run on exactly one of \"symbols\"")),
+        }
+    }
+
+    pub fn hypertune_config(&self) -> Result<HypertuneConfig> {
+        let mut hypertune = self
+            .hypertune
+            .clone()
+            .ok_or_else(|| anyhow!("Config has no \"hypertune\" section"))?;
+        hypertune.backtest = self.backtest_config();
+        Ok(hypertune)
+    }
+
+    pub fn sync_intervals(&self) -> Vec<KlineInterval> {
+        if self.sync.intervals.is_empty() {
+            vec![self.interval]
+        } else {
+            self.sync.intervals.clone()
+        }
+    }
+
+    pub fn live_config(&self) -> Result<LiveConfig> {
+        let api_key = self.exchange.api_key.resolve()?;
+        let secret_key = self.exchange.secret_key.resolve()?;
+        if api_key.is_empty() || secret_key.is_empty() {
+            return Err(anyhow!(
+                "Config \"exchange.api_key\" and \"exchange.secret_key\" are required in live mode"
+            ));
+        }
+        if self.symbols.len() != 1 {
+            return Err(anyhow!("Live mode trades exactly one of \"symbols\""));
+        }
+        Ok(LiveConfig {
+            api_key,
+            secret_key,
+            symbol: self.symbols[0].clone(),
+            interval: self.interval,
+            fixed_update: self.fixed_update.clone(),
+            history_limit: self.live.history_limit,
+            poll_interval_ms: self.live.poll_interval_ms,
+            fee_rate: self.live.fee_rate,
+            strategy: self.strategy.clone(),
+            mongo: self.mongo.clone(),
+            risk: self.risk.clone(),
+        })
+    }
+}
+
+fn apply_override(value: &mut Value, arg: &str) -> Result<()> {
+    let (key, raw) = arg
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid override \"{}\", expected key=value", arg))?;
+    let new_value =
+        serde_json::from_str::<Value>(raw).unwrap_or_else(|_| Value::String(raw.to_owned()));
+    let mut target = value;
+    for part in key.split('.') {
+        if !target.is_object() {
+            *target = Value::Object(Default::default());
+        }
+        target = target
+            .as_object_mut()
+            .unwrap()
+            .entry(part.to_owned())
+            .or_insert(Value::Null);
+    }
+    *target = new_value;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtest::costs::FeeModel;
+
+    const TOML_CONFIG: &str = r#"
+symbols = ["BTCUSDT"]
+interval = "1h"
+fixed_update = { Hour = 4 }
+
+[exchange]
+api_key = "plain-key"
+secret_key = { env = "TRADE_UTILS_TEST_SECRET" }
+
+[strategy]
+name = "sma_cross"
+fast = 10
+
+[backtest]
+csv_path = "klines.csv"
+
+[backtest.exchange]
+initial_balance = 5000.0
+fees = { vip_tier = 1 }
+"#;
+
+    const YAML_CONFIG: &str = r#"
+symbols: [BTCUSDT]
+interval: 1h
+fixed_update: { Hour: 4 }
+exchange:
+  api_key: plain-key
+  secret_key: { env: TRADE_UTILS_TEST_SECRET }
+strategy:
+  name: sma_cross
+  fast: 10
+backtest:
+  csv_path: klines.csv
+  exchange:
+    initial_balance: 5000.0
+    fees: { vip_tier: 1 }
+"#;
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "trade_utils_config_{}_{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn load(name: &str, content: &str, overrides: &[&str]) -> Result<Config> {
+        let path = write_config(name, content);
+        let overrides: Vec<_> = overrides.iter().map(|o| o.to_string()).collect();
+        let config = Config::from_path(&path, &overrides);
+        std::fs::remove_file(path).unwrap();
+        config
+    }
+
+    #[test]
+    fn toml_and_yaml_load_the_same_config() {
+        let config = load("same.toml", TOML_CONFIG, &[]).unwrap();
+        let yaml = load("same.yaml", YAML_CONFIG, &[]).unwrap();
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::to_value(&yaml).unwrap()
+        );
+
+        assert_eq!(config.interval, KlineInterval::Hour1);
+        assert_eq!(config.fixed_update, Some(FixedUpdate::Hour(4)));
+        assert_eq!(config.strategy["fast"], 10);
+        assert_eq!(config.live.history_limit, 500);
+        // A partial exchange block keeps the defaults of the rest
+        let exchange = &config.backtest.config.exchange;
+        assert_eq!(exchange.initial_balance, 5000.);
+        assert_eq!(exchange.leverage, 1.);
+        assert_eq!(
+            exchange.fees,
+            Some(FeeModel {
+                vip_tier: 1,
+                ..Default::default()
+            })
+        );
+    }
+
+    /// TOML has no null, absent options are left out instead.
+    fn without_nulls(value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .filter(|(_, v)| !v.is_null())
+                    .map(|(k, v)| (k, without_nulls(v)))
+                    .collect(),
+            ),
+            Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+            value => value,
+        }
+    }
+
+    #[test]
+    fn toml_and_yaml_round_trip() {
+        let config = load("round_trip.toml", TOML_CONFIG, &[]).unwrap();
+        let value = serde_json::to_value(&config).unwrap();
+
+        let toml = toml::Value::try_from(without_nulls(value.clone()))
+            .unwrap()
+            .to_string();
+        let from_toml = load("round_trip_2.toml", &toml, &[]).unwrap();
+        assert_eq!(serde_json::to_value(&from_toml).unwrap(), value);
+
+        let yaml = serde_yaml::to_string(&value).unwrap();
+        let from_yaml = load("round_trip.yaml", &yaml, &[]).unwrap();
+        assert_eq!(serde_json::to_value(&from_yaml).unwrap(), value);
+    }
+
+    #[test]
+    fn overrides_replace_and_create_keys() {
+        let config = load(
+            "overrides.toml",
+            TOML_CONFIG,
+            &[
+                "interval=4h",
+                "backtest.exchange.leverage=3",
+                "strategy.slow=50",
+                "live.poll_interval_ms=250",
+                "mongo.connection_string=mongodb://localhost",
+                "mongo.database=trading",
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.interval, KlineInterval::Hour4);
+        assert_eq!(config.backtest.config.exchange.leverage, 3.);
+        assert_eq!(config.strategy["slow"], 50);
+        assert_eq!(config.live.poll_interval_ms, 250);
+        let mongo = config.mongo.unwrap();
+        assert_eq!(mongo.database, "trading");
+        assert_eq!(mongo.trade_collection, "trades");
+
+        let mut value = serde_json::json!({});
+        assert!(apply_override(&mut value, "no_equals_sign").is_err());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_values() {
+        for (name, overrides, message) in [
+            ("symbols", vec!["symbols=[]"], "symbols"),
+            (
+                "leverage",
+                vec!["backtest.exchange.leverage=0"],
+                "backtest.exchange.leverage",
+            ),
+            (
+                "balance",
+                vec!["backtest.exchange.initial_balance=-1"],
+                "backtest.exchange.initial_balance",
+            ),
+            (
+                "fixed_update",
+                vec![r#"fixed_update={"Minute":0}"#],
+                "fixed_update",
+            ),
+            (
+                "deviation",
+                vec!["risk.max_price_deviation=0"],
+                "risk.max_price_deviation",
+            ),
+        ] {
+            let error = load(&format!("{}.toml", name), TOML_CONFIG, &overrides)
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains(message), "{}: {}", name, error);
+        }
+        assert!(load("unknown.ini", TOML_CONFIG, &[]).is_err());
+        assert!(load("bad_interval.toml", TOML_CONFIG, &["interval=7m"]).is_err());
+    }
+
+    #[test]
+    fn secrets_are_redacted_and_resolved_from_env() {
+        let config = load("secrets.toml", TOML_CONFIG, &[]).unwrap();
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("plain-key"));
+        assert!(debug.contains("Plain(<redacted>)"));
+        assert!(debug.contains("TRADE_UTILS_TEST_SECRET"));
+
+        assert_eq!(config.exchange.api_key.resolve().unwrap(), "plain-key");
+        std::env::set_var("TRADE_UTILS_TEST_SECRET", "from-env");
+        assert_eq!(config.exchange.secret_key.resolve().unwrap(), "from-env");
+        let missing = Secret::Env {
+            env: "TRADE_UTILS_TEST_MISSING".to_owned(),
+        };
+        assert!(missing.resolve().is_err());
+    }
+}
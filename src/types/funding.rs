@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Funding rate settled at `funding_time`, longs pay shorts `rate` times the
+/// position notional when it's positive.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FundingRate {
+    pub symbol: String,
+    pub funding_time: i64,
+    pub rate: f64,
+}
@@ -6,4 +6,12 @@ pub struct InstrumentInfo {
     pub tick_size: String,
     pub lot_size: String,
     pub min_qty: f64,
+    #[serde(default)]
+    pub min_notional: f64,
+}
+
+impl InstrumentInfo {
+    pub fn step_size(&self) -> f64 {
+        self.lot_size.parse().unwrap_or(0.)
+    }
 }
@@ -0,0 +1,292 @@
+use anyhow::{anyhow, Error, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::timer::FixedUpdate;
+
+const MINUTE_MS: i64 = 60 * 1000;
+const HOUR_MS: i64 = 60 * MINUTE_MS;
+const DAY_MS: i64 = 24 * HOUR_MS;
+// 1970-01-05 is the first Monday after the epoch, weekly klines start on Mondays
+const FIRST_MONDAY_MS: i64 = 4 * DAY_MS;
+
+/// Kline intervals supported by Binance futures, written as Binance spells
+/// them, e.g. "15m", "4h", "1M".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum KlineInterval {
+    Minute1,
+    Minute3,
+    Minute5,
+    Minute15,
+    Minute30,
+    Hour1,
+    Hour2,
+    Hour4,
+    Hour6,
+    Hour8,
+    Hour12,
+    Day1,
+    Day3,
+    Week1,
+    Month1,
+}
+
+impl KlineInterval {
+    pub const ALL: [KlineInterval; 15] = [
+        KlineInterval::Minute1,
+        KlineInterval::Minute3,
+        KlineInterval::Minute5,
+        KlineInterval::Minute15,
+        KlineInterval::Minute30,
+        KlineInterval::Hour1,
+        KlineInterval::Hour2,
+        KlineInterval::Hour4,
+        KlineInterval::Hour6,
+        KlineInterval::Hour8,
+        KlineInterval::Hour12,
+        KlineInterval::Day1,
+        KlineInterval::Day3,
+        KlineInterval::Week1,
+        KlineInterval::Month1,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KlineInterval::Minute1 => "1m",
+            KlineInterval::Minute3 => "3m",
+            KlineInterval::Minute5 => "5m",
+            KlineInterval::Minute15 => "15m",
+            KlineInterval::Minute30 => "30m",
+            KlineInterval::Hour1 => "1h",
+            KlineInterval::Hour2 => "2h",
+            KlineInterval::Hour4 => "4h",
+            KlineInterval::Hour6 => "6h",
+            KlineInterval::Hour8 => "8h",
+            KlineInterval::Hour12 => "12h",
+            KlineInterval::Day1 => "1d",
+            KlineInterval::Day3 => "3d",
+            KlineInterval::Week1 => "1w",
+            KlineInterval::Month1 => "1M",
+        }
+    }
+
+    /// Length of the interval, nominally 30 days for `Month1` whose real
+    /// length depends on the month, see `bucket`.
+    pub fn duration_ms(&self) -> i64 {
+        match self {
+            KlineInterval::Minute1 => MINUTE_MS,
+            KlineInterval::Minute3 => 3 * MINUTE_MS,
+            KlineInterval::Minute5 => 5 * MINUTE_MS,
+            KlineInterval::Minute15 => 15 * MINUTE_MS,
+            KlineInterval::Minute30 => 30 * MINUTE_MS,
+            KlineInterval::Hour1 => HOUR_MS,
+            KlineInterval::Hour2 => 2 * HOUR_MS,
+            KlineInterval::Hour4 => 4 * HOUR_MS,
+            KlineInterval::Hour6 => 6 * HOUR_MS,
+            KlineInterval::Hour8 => 8 * HOUR_MS,
+            KlineInterval::Hour12 => 12 * HOUR_MS,
+            KlineInterval::Day1 => DAY_MS,
+            KlineInterval::Day3 => 3 * DAY_MS,
+            KlineInterval::Week1 => 7 * DAY_MS,
+            KlineInterval::Month1 => 30 * DAY_MS,
+        }
+    }
+
+    /// Open and close time of the kline containing the timestamp, aligned to
+    /// UTC boundaries the same way Binance aligns its klines. Fails for
+    /// `Month1` if the timestamp is out of chrono's date range.
+    pub fn bucket(&self, ts: i64) -> Result<(i64, i64)> {
+        match self {
+            KlineInterval::Month1 => {
+                let datetime = NaiveDateTime::from_timestamp_millis(ts)
+                    .ok_or_else(|| anyhow!("Invalid timestamp: {}", ts))?;
+                let month_start = |year: i32, month: u32| -> i64 {
+                    let date = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+                    NaiveDateTime::new(date, NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+                        .timestamp_millis()
+                };
+                let (year, month) = (datetime.year(), datetime.month());
+                let (next_year, next_month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                Ok((
+                    month_start(year, month),
+                    month_start(next_year, next_month) - 1,
+                ))
+            }
+            _ => {
+                let offset = match self {
+                    KlineInterval::Week1 => FIRST_MONDAY_MS,
+                    _ => 0,
+                };
+                let period = self.duration_ms();
+                let open_ts = ts - (ts - offset).rem_euclid(period);
+                Ok((open_ts, open_ts + period - 1))
+            }
+        }
+    }
+}
+
+impl fmt::Display for KlineInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for KlineInterval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        KlineInterval::ALL
+            .iter()
+            .find(|interval| interval.as_str() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("Invalid kline interval: {}", s))
+    }
+}
+
+impl TryFrom<String> for KlineInterval {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<KlineInterval> for String {
+    fn from(interval: KlineInterval) -> Self {
+        interval.as_str().to_owned()
+    }
+}
+
+impl TryFrom<KlineInterval> for FixedUpdate {
+    type Error = Error;
+
+    fn try_from(interval: KlineInterval) -> Result<Self> {
+        match interval {
+            KlineInterval::Month1 => Err(anyhow!("1M has no fixed update")),
+            // Weeks open on Monday, `FixedUpdate::Day(7)` boundaries fall on Thursday
+            KlineInterval::Week1 => Err(anyhow!("1w has no fixed update")),
+            _ => {
+                let ms = interval.duration_ms();
+                if ms % DAY_MS == 0 {
+                    Ok(FixedUpdate::Day(ms / DAY_MS))
+                } else if ms % HOUR_MS == 0 {
+                    Ok(FixedUpdate::Hour(ms / HOUR_MS))
+                } else {
+                    Ok(FixedUpdate::Minute(ms / MINUTE_MS))
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<FixedUpdate> for KlineInterval {
+    type Error = Error;
+
+    fn try_from(fixed_update: FixedUpdate) -> Result<Self> {
+        let ms = fixed_update.duration_ms();
+        KlineInterval::ALL
+            .iter()
+            .filter(|interval| !matches!(interval, KlineInterval::Week1 | KlineInterval::Month1))
+            .find(|interval| interval.duration_ms() == ms)
+            .copied()
+            .ok_or_else(|| anyhow!("No kline interval matches {:?}", fixed_update))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(year: i32, month: u32, day: u32) -> i64 {
+        let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        NaiveDateTime::new(date, NaiveTime::from_hms_opt(0, 0, 0).unwrap()).timestamp_millis()
+    }
+
+    #[test]
+    fn every_interval_round_trips_through_its_name() {
+        for interval in KlineInterval::ALL {
+            let name = interval.to_string();
+            assert_eq!(name.parse::<KlineInterval>().unwrap(), interval);
+            let json = serde_json::to_string(&interval).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            assert_eq!(
+                serde_json::from_str::<KlineInterval>(&json).unwrap(),
+                interval
+            );
+        }
+        assert!("1M".parse::<KlineInterval>().is_ok());
+        assert!("1mo".parse::<KlineInterval>().is_err());
+        assert!("2m".parse::<KlineInterval>().is_err());
+    }
+
+    #[test]
+    fn month_buckets_cross_year_ends() {
+        let month = KlineInterval::Month1;
+        let december = (ts(2022, 12, 1), ts(2023, 1, 1) - 1);
+        assert_eq!(month.bucket(ts(2022, 12, 1)).unwrap(), december);
+        assert_eq!(month.bucket(ts(2022, 12, 31) + 1000).unwrap(), december);
+        assert_eq!(month.bucket(ts(2023, 1, 1) - 1).unwrap(), december);
+        assert_eq!(
+            month.bucket(ts(2023, 1, 1)).unwrap(),
+            (ts(2023, 1, 1), ts(2023, 2, 1) - 1)
+        );
+        // Leap year February
+        assert_eq!(
+            month.bucket(ts(2024, 2, 29)).unwrap(),
+            (ts(2024, 2, 1), ts(2024, 3, 1) - 1)
+        );
+        assert!(month.bucket(i64::MAX).is_err());
+    }
+
+    #[test]
+    fn fixed_buckets_align_to_utc() {
+        let ts = ts(2023, 1, 4) + 5 * HOUR_MS + 17 * MINUTE_MS; // a Wednesday
+        assert_eq!(
+            KlineInterval::Minute15.bucket(ts).unwrap(),
+            (ts - 2 * MINUTE_MS, ts + 13 * MINUTE_MS - 1)
+        );
+        let day_open = ts - 5 * HOUR_MS - 17 * MINUTE_MS;
+        assert_eq!(
+            KlineInterval::Hour4.bucket(ts).unwrap(),
+            (day_open + 4 * HOUR_MS, day_open + 8 * HOUR_MS - 1)
+        );
+        // Weeks open on Monday 2023-01-02
+        assert_eq!(
+            KlineInterval::Week1.bucket(ts).unwrap(),
+            (day_open - 2 * DAY_MS, day_open + 5 * DAY_MS - 1)
+        );
+    }
+
+    #[test]
+    fn fixed_update_conversions() {
+        let pairs = [
+            (KlineInterval::Minute1, FixedUpdate::Minute(1)),
+            (KlineInterval::Minute30, FixedUpdate::Minute(30)),
+            (KlineInterval::Hour1, FixedUpdate::Hour(1)),
+            (KlineInterval::Hour12, FixedUpdate::Hour(12)),
+            (KlineInterval::Day1, FixedUpdate::Day(1)),
+            (KlineInterval::Day3, FixedUpdate::Day(3)),
+        ];
+        for (interval, fixed_update) in pairs {
+            assert_eq!(FixedUpdate::try_from(interval).unwrap(), fixed_update);
+            assert_eq!(KlineInterval::try_from(fixed_update).unwrap(), interval);
+        }
+        assert!(FixedUpdate::try_from(KlineInterval::Week1).is_err());
+        assert!(FixedUpdate::try_from(KlineInterval::Month1).is_err());
+        // Same length as a listed interval, spelled differently
+        assert_eq!(
+            KlineInterval::try_from(FixedUpdate::Minute(60)).unwrap(),
+            KlineInterval::Hour1
+        );
+        assert!(KlineInterval::try_from(FixedUpdate::Minute(7)).is_err());
+        assert!(KlineInterval::try_from(FixedUpdate::Day(7)).is_err());
+    }
+}
@@ -8,4 +8,15 @@ pub struct Kline {
     pub high: f64,
     pub low: f64,
     pub close: f64,
+    // Missing in klines stored before volumes were kept, read as 0
+    #[serde(default)]
+    pub volume: f64,
+    #[serde(default)]
+    pub quote_volume: f64,
+    #[serde(default)]
+    pub trade_count: i64,
+    #[serde(default)]
+    pub taker_buy_base_volume: f64,
+    #[serde(default)]
+    pub taker_buy_quote_volume: f64,
 }
@@ -1,7 +1,11 @@
 pub mod account;
 pub mod cli;
+pub mod config;
+pub mod funding;
 pub mod instrument;
+pub mod interval;
 pub mod kline;
 pub mod order;
+pub mod schedule;
 pub mod timer;
 pub mod trade;
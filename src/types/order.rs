@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fmt;
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OrderType {
     Market,
 }
@@ -12,7 +15,7 @@ impl fmt::Display for OrderType {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OrderSide {
     Buy,
     Sell,
@@ -27,6 +30,7 @@ impl fmt::Display for OrderSide {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TimeInForce {
     Gtc,
 }
@@ -39,6 +43,7 @@ impl fmt::Display for TimeInForce {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub symbol: String,
     pub size: f64, // quantity in binance
@@ -62,3 +67,25 @@ impl Order {
         }
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub symbol: String,
+    pub order_side: OrderSide,
+    pub size: f64,
+    pub price: f64,
+    pub fee: f64,
+    pub ts: i64,
+}
+
+/// An order submission and what the exchange answered, kept for auditing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderRecord {
+    pub ts: i64,
+    pub symbol: String,
+    pub order: Order,
+    pub tp_price: f64,
+    pub sl_price: f64,
+    pub response: Option<Value>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,369 @@
+use anyhow::{anyhow, Error, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::timer::FixedUpdate;
+
+const SECOND_MS: i64 = 1000;
+// Long enough to find Feb 29 from anywhere but 2100
+const SEARCH_YEARS: i32 = 8;
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const DAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// When a `Timer` fires. In config files either a `FixedUpdate`, e.g.
+/// `{ Hour = 4 }`, or a cron expression string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Schedule {
+    Fixed(FixedUpdate),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// First firing time strictly after the timestamp, `i64::MAX` if the
+    /// schedule never fires again.
+    pub fn next_after(&self, ts: i64) -> i64 {
+        match self {
+            Schedule::Fixed(fixed_update) => fixed_update.align(ts) + fixed_update.duration_ms(),
+            Schedule::Cron(cron) => cron.next_after(ts).unwrap_or(i64::MAX),
+        }
+    }
+}
+
+impl From<FixedUpdate> for Schedule {
+    fn from(fixed_update: FixedUpdate) -> Self {
+        Schedule::Fixed(fixed_update)
+    }
+}
+
+impl From<CronSchedule> for Schedule {
+    fn from(cron: CronSchedule) -> Self {
+        Schedule::Cron(cron)
+    }
+}
+
+/// Cron expression evaluated in UTC, `sec min hour day-of-month month
+/// day-of-week` with the seconds field optional. Fields take `*`, values,
+/// `a-b` ranges, `/step` and comma lists, months and weekdays also take
+/// names. As in cron a day matches either day field when both are set.
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are accepted.
+///
+/// e.g. "0 0 * * MON" every Monday 00:00, "0 55 7,15,23 * * *" 5 minutes
+/// before each funding time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expression: String,
+    seconds: u64, // bit per allowed value
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64, // 0 is Sunday
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// First matching second strictly after the timestamp.
+    pub fn next_after(&self, ts: i64) -> Option<i64> {
+        let start = ts.div_euclid(SECOND_MS) * SECOND_MS + SECOND_MS;
+        let mut datetime = NaiveDateTime::from_timestamp_millis(start)?;
+        let end_year = datetime.year() + SEARCH_YEARS;
+        while datetime.year() <= end_year {
+            if !has(self.months, datetime.month()) {
+                let (year, month) = match datetime.month() {
+                    12 => (datetime.year() + 1, 1),
+                    month => (datetime.year(), month + 1),
+                };
+                datetime = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(datetime.date()) {
+                datetime = datetime.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, datetime.hour()) {
+                datetime = truncate(datetime, 0, 0) + Duration::hours(1);
+            } else if !has(self.minutes, datetime.minute()) {
+                datetime = truncate(datetime, datetime.minute(), 0) + Duration::minutes(1);
+            } else if !has(self.seconds, datetime.second()) {
+                datetime += Duration::seconds(1);
+            } else {
+                return Some(datetime.timestamp_millis());
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day_of_month = has(self.days_of_month, date.day());
+        let day_of_week = has(self.days_of_week, date.weekday().num_days_from_sunday());
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+}
+
+fn has(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+fn truncate(datetime: NaiveDateTime, minute: u32, second: u32) -> NaiveDateTime {
+    datetime
+        .date()
+        .and_hms_opt(datetime.hour(), minute, second)
+        .unwrap()
+}
+
+/// Parse one field into a bitmask of the values it allows. Returns whether
+/// the field was `*`, cron treats unrestricted day fields specially.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<(u64, bool)> {
+    let value = |s: &str| -> Result<u32> {
+        let upper = s.to_uppercase();
+        let named = names
+            .iter()
+            .position(|name| *name == upper)
+            .map(|i| i as u32 + min);
+        let value = match named {
+            Some(value) => value,
+            None => s
+                .parse::<u32>()
+                .map_err(|_| anyhow!("Invalid cron value: {}", s))?,
+        };
+        if value < min || value > max {
+            return Err(anyhow!("Cron value {} outside {}-{}", value, min, max));
+        }
+        Ok(value)
+    };
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .map_err(|_| anyhow!("Invalid cron step: {}", part))?;
+                if step == 0 {
+                    return Err(anyhow!("Invalid cron step: {}", part));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (value(from)?, value(to)?),
+                // "a/n" runs from a to the end of the field
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if from > to {
+            return Err(anyhow!("Invalid cron range: {}", part));
+        }
+        for v in (from..=to).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok((mask, field == "*"))
+}
+
+impl FromStr for CronSchedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let expression = s.trim();
+        let expanded = match expression {
+            "@yearly" | "@annually" => "0 0 0 1 1 *",
+            "@monthly" => "0 0 0 1 * *",
+            "@weekly" => "0 0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 0 * * *",
+            "@hourly" => "0 0 * * * *",
+            _ => expression,
+        };
+        let mut fields = expanded.split_whitespace().collect::<Vec<_>>();
+        match fields.len() {
+            5 => fields.insert(0, "0"),
+            6 => {}
+            _ => {
+                return Err(anyhow!(
+                    "Cron expression needs 5 or 6 fields: {}",
+                    expression
+                ))
+            }
+        }
+        let (seconds, _) = parse_field(fields[0], 0, 59, &[])?;
+        let (minutes, _) = parse_field(fields[1], 0, 59, &[])?;
+        let (hours, _) = parse_field(fields[2], 0, 23, &[])?;
+        let (days_of_month, any_day_of_month) = parse_field(fields[3], 1, 31, &[])?;
+        let (months, _) = parse_field(fields[4], 1, 12, &MONTH_NAMES)?;
+        let (mut days_of_week, any_day_of_week) = parse_field(fields[5], 0, 7, &DAY_NAMES)?;
+        // 7 is Sunday as well
+        if has(days_of_week, 7) {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+        let cron = CronSchedule {
+            expression: expression.to_owned(),
+            seconds,
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            any_day_of_month,
+            any_day_of_week,
+        };
+        if cron.next_after(0).is_none() {
+            return Err(anyhow!("Cron expression never fires: {}", expression));
+        }
+        Ok(cron)
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(cron: CronSchedule) -> Self {
+        cron.expression
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(datetime: &str) -> i64 {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn next(expression: &str, after: &str) -> i64 {
+        let cron = expression.parse::<CronSchedule>().unwrap();
+        cron.next_after(ts(after)).unwrap()
+    }
+
+    #[test]
+    fn next_after_is_strictly_later() {
+        assert_eq!(
+            next("0 55 7,15,23 * * *", "2022-01-01 08:00:00"),
+            ts("2022-01-01 15:55:00")
+        );
+        assert_eq!(
+            next("0 55 7,15,23 * * *", "2022-01-01 23:55:00"),
+            ts("2022-01-02 07:55:00")
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2022-01-01 10:14:59"),
+            ts("2022-01-01 10:15:00")
+        );
+        assert_eq!(
+            next("30 9-17/4 * * *", "2022-01-01 14:00:00"),
+            ts("2022-01-01 17:30:00")
+        );
+    }
+
+    #[test]
+    fn macros_and_names() {
+        assert_eq!(
+            next("@hourly", "2022-01-01 10:00:00"),
+            ts("2022-01-01 11:00:00")
+        );
+        assert_eq!(
+            next("@monthly", "2022-01-15 00:00:00"),
+            ts("2022-02-01 00:00:00")
+        );
+        // 2022-01-01 is a Saturday
+        assert_eq!(
+            next("@weekly", "2022-01-01 00:00:00"),
+            ts("2022-01-02 00:00:00")
+        );
+        assert_eq!(
+            next("0 0 * * MON", "2022-01-01 00:00:00"),
+            ts("2022-01-03 00:00:00")
+        );
+        assert_eq!(
+            next("0 0 * * 7", "2022-01-01 00:00:00"),
+            ts("2022-01-02 00:00:00")
+        );
+        assert_eq!(
+            next("0 0 1 jun *", "2022-01-01 00:00:00"),
+            ts("2022-06-01 00:00:00")
+        );
+    }
+
+    #[test]
+    fn either_day_field_matches_when_both_are_set() {
+        // Fridays and the 13th, Friday 2022-01-07 comes first
+        assert_eq!(
+            next("0 0 13 * FRI", "2022-01-01 00:00:00"),
+            ts("2022-01-07 00:00:00")
+        );
+        assert_eq!(
+            next("0 0 13 * FRI", "2022-01-07 00:00:00"),
+            ts("2022-01-13 00:00:00")
+        );
+        // With the day of week unrestricted only the 13th matches
+        assert_eq!(
+            next("0 0 13 * *", "2022-01-01 00:00:00"),
+            ts("2022-01-13 00:00:00")
+        );
+    }
+
+    #[test]
+    fn finds_the_next_leap_day() {
+        assert_eq!(
+            next("0 0 29 2 *", "2022-03-01 00:00:00"),
+            ts("2024-02-29 00:00:00")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in [
+            "* * *",
+            "* * * * * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 * FOO *",
+            "0 0 30 2 *",
+        ] {
+            assert!(
+                expression.parse::<CronSchedule>().is_err(),
+                "{expression} parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn deserializes_fixed_or_cron() {
+        let fixed: Schedule = serde_json::from_str(r#"{"Hour": 4}"#).unwrap();
+        assert_eq!(fixed, Schedule::Fixed(FixedUpdate::Hour(4)));
+        let cron: Schedule = serde_json::from_str(r#""@daily""#).unwrap();
+        assert_eq!(
+            cron.next_after(ts("2022-01-01 12:00:00")),
+            ts("2022-01-02 00:00:00")
+        );
+        assert!(serde_json::from_str::<Schedule>(r#""0 0 31 2 *""#).is_err());
+    }
+}
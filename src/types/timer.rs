@@ -1,11 +1,83 @@
-use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
-use log::info;
+use chrono::{Duration, NaiveDateTime, Utc};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use crate::types::schedule::Schedule;
+
+pub trait Clock: std::fmt::Debug {
+    fn now_ms(&self) -> i64;
+
+    /// Wait for `ms` milliseconds of this clock's time to pass.
+    fn sleep_ms(&self, ms: i64) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+
+    fn sleep_ms(&self, ms: i64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let duration = std::time::Duration::from_millis(ms.max(0) as u64);
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+/// Clock that only moves when told to. Clones share the same time, so a
+/// backtest can keep one and advance the `Timer` holding the other.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedClock {
+    ts: Arc<AtomicI64>,
+}
+
+impl SimulatedClock {
+    pub fn new(ts: i64) -> Self {
+        SimulatedClock {
+            ts: Arc::new(AtomicI64::new(ts)),
+        }
+    }
+
+    pub fn set(&self, ts: i64) {
+        self.ts.store(ts, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, ms: i64) {
+        self.ts.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_ms(&self) -> i64 {
+        self.ts.load(Ordering::SeqCst)
+    }
+
+    /// Jumps straight to the wake up time.
+    fn sleep_ms(&self, ms: i64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.advance(ms.max(0));
+        Box::pin(std::future::ready(()))
+    }
+}
 
 #[derive(Debug, Clone)]
-pub struct Timer {
+pub struct Timer<C: Clock = SystemClock> {
     datetime: NaiveDateTime,
-    fixed_update: FixedUpdate,
+    schedule: Schedule,
+    clock: C,
+    offset_ms: i64, // fire this long after each boundary
+    next_ts: i64,   // boundary of the next `tick`
+}
+
+/// A boundary reached by `Timer::tick`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tick {
+    pub ts: i64,      // boundary, without the offset
+    pub skipped: i64, // boundaries missed since the previous tick
 }
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -15,89 +87,270 @@ pub enum FixedUpdate {
     Day(i64),
 }
 
+impl FixedUpdate {
+    /// Length of the period, at least 1ms so that a zero or negative value
+    /// can't divide by zero or stall a timer. `Config::validate` rejects them.
+    pub fn duration_ms(&self) -> i64 {
+        let duration = match self {
+            FixedUpdate::Minute(m) => Duration::minutes(*m),
+            FixedUpdate::Hour(h) => Duration::hours(*h),
+            FixedUpdate::Day(d) => Duration::days(*d),
+        };
+        duration.num_milliseconds().max(1)
+    }
+
+    /// Periods must be positive.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            FixedUpdate::Minute(v) | FixedUpdate::Hour(v) | FixedUpdate::Day(v) => *v > 0,
+        }
+    }
+
+    /// Latest boundary at or before the timestamp. Boundaries are multiples
+    /// of the period since the epoch, e.g. 00, 04, 08 UTC for `Hour(4)`.
+    pub fn align(&self, ts: i64) -> i64 {
+        ts - ts.rem_euclid(self.duration_ms())
+    }
+}
+
 impl Timer {
-    pub fn new(fixed_update: FixedUpdate) -> Self {
-        let now = Utc::now();
-        let datetime = NaiveDateTime::from_timestamp_millis(now.timestamp_millis()).unwrap();
+    pub fn new(schedule: impl Into<Schedule>) -> Self {
+        Timer::with_clock(schedule, SystemClock)
+    }
+}
+
+impl<C: Clock> Timer<C> {
+    pub fn with_clock(schedule: impl Into<Schedule>, clock: C) -> Self {
+        let schedule = schedule.into();
+        let now = clock.now_ms();
+        // Latest boundary at or before now, `get_ts_ms` until the first update
+        let start = match &schedule {
+            Schedule::Fixed(fixed_update) => fixed_update.align(now),
+            Schedule::Cron(_) => now,
+        };
         let mut timer = Timer {
-            datetime,
-            fixed_update: fixed_update.clone(),
+            datetime: NaiveDateTime::from_timestamp_millis(start).unwrap(),
+            schedule,
+            clock,
+            offset_ms: 0,
+            next_ts: 0,
         };
-        match fixed_update {
-            // Pad the datetime to 0 based
-            FixedUpdate::Minute(_) => {
-                let curr_ts = NaiveDateTime::new(
-                    NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
-                    NaiveTime::from_hms_opt(now.hour(), now.minute(), 0).unwrap(),
-                )
-                .timestamp_millis();
-                timer.datetime = NaiveDateTime::from_timestamp_millis(curr_ts).unwrap();
-            }
-            FixedUpdate::Hour(_) => {
-                let curr_ts = NaiveDateTime::new(
-                    NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
-                    NaiveTime::from_hms_opt(now.hour(), 0, 0).unwrap(),
-                )
-                .timestamp_millis();
-                timer.datetime = NaiveDateTime::from_timestamp_millis(curr_ts).unwrap();
-            }
-            FixedUpdate::Day(_) => {
-                let curr_ts = NaiveDateTime::new(
-                    NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
-                    NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
-                )
-                .timestamp_millis();
-                timer.datetime = NaiveDateTime::from_timestamp_millis(curr_ts).unwrap();
-            }
-        }
+        timer.next_ts = timer.next_boundary();
         info!("Start timer: {:?}", timer);
         timer
     }
 
-    pub fn update(&mut self) -> bool {
-        let now = Utc::now();
-        let prev = self.datetime;
-        let curr = now.naive_utc();
-        let mut result = false;
-        match self.fixed_update {
-            FixedUpdate::Minute(minutes) => {
-                let minute_duration = Duration::minutes(minutes);
-                if prev.checked_add_signed(minute_duration).unwrap() <= curr {
-                    self.datetime = NaiveDateTime::new(
-                        NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
-                        NaiveTime::from_hms_opt(now.hour(), now.minute(), 0).unwrap(),
-                    );
-                    result = true;
-                }
+    /// Fire `tick` this long after each boundary, e.g. a few seconds after a
+    /// kline closes to let the exchange finalize it.
+    pub fn with_offset(mut self, offset_ms: i64) -> Self {
+        self.offset_ms = offset_ms;
+        self.next_ts = self.next_boundary();
+        self
+    }
+
+    fn next_boundary(&self) -> i64 {
+        self.schedule
+            .next_after(self.clock.now_ms() - self.offset_ms)
+    }
+
+    /// Sleep until the next boundary plus the offset. Ticks stay aligned to
+    /// the boundaries, after a stall the latest boundary passed is returned
+    /// along with the number of boundaries skipped.
+    pub async fn tick(&mut self) -> Tick {
+        loop {
+            let wait_ms = self.next_ts + self.offset_ms - self.clock.now_ms();
+            if wait_ms <= 0 {
+                break;
             }
-            FixedUpdate::Hour(hours) => {
-                let hour_duration = Duration::hours(hours);
-                if prev.checked_add_signed(hour_duration).unwrap() <= curr {
-                    self.datetime = NaiveDateTime::new(
-                        NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
-                        NaiveTime::from_hms_opt(now.hour(), 0, 0).unwrap(),
-                    );
-                    result = true;
-                }
+            self.clock.sleep_ms(wait_ms).await;
+        }
+        let now = self.clock.now_ms() - self.offset_ms;
+        let (ts, skipped) = match &self.schedule {
+            Schedule::Fixed(fixed_update) => {
+                let ts = fixed_update.align(now).max(self.next_ts);
+                (ts, (ts - self.next_ts) / fixed_update.duration_ms())
             }
-            FixedUpdate::Day(days) => {
-                let day_duration = Duration::days(days);
-                if prev.checked_add_signed(day_duration).unwrap() <= curr {
-                    self.datetime = NaiveDateTime::new(
-                        NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
-                        NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
-                    );
-                    result = true;
+            Schedule::Cron(_) => {
+                let (mut ts, mut skipped) = (self.next_ts, 0);
+                loop {
+                    let next_ts = self.schedule.next_after(ts);
+                    if next_ts > now {
+                        break;
+                    }
+                    ts = next_ts;
+                    skipped += 1;
                 }
+                (ts, skipped)
             }
+        };
+        if skipped > 0 {
+            warn!("Timer skipped {} ticks before {}", skipped, ts);
         }
-        if result {
-            println!("Update timer from {:?} to {:?}", prev, self.datetime);
+        self.datetime = NaiveDateTime::from_timestamp_millis(ts).unwrap();
+        self.next_ts = self.schedule.next_after(ts);
+        Tick { ts, skipped }
+    }
+
+    /// Non-blocking version of `tick`: true once the next boundary plus the
+    /// offset has passed, `get_ts_ms` is then the latest boundary passed.
+    pub fn update(&mut self) -> bool {
+        let now = self.clock.now_ms() - self.offset_ms;
+        if self.next_ts > now {
+            return false;
         }
-        result
+        let prev = self.datetime;
+        while self.next_ts <= now {
+            self.datetime = NaiveDateTime::from_timestamp_millis(self.next_ts).unwrap();
+            self.next_ts = self.schedule.next_after(self.next_ts);
+        }
+        info!("Update timer from {:?} to {:?}", prev, self.datetime);
+        true
     }
 
     pub fn get_ts_ms(&self) -> i64 {
         self.datetime.timestamp_millis()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task::block_on;
+
+    use crate::types::schedule::CronSchedule;
+
+    const MINUTE_MS: i64 = 60_000;
+    const HOUR_MS: i64 = 60 * MINUTE_MS;
+
+    #[test]
+    fn align_to_period_boundaries() {
+        assert_eq!(FixedUpdate::Hour(4).align(5 * HOUR_MS + 1), 4 * HOUR_MS);
+        assert_eq!(FixedUpdate::Hour(4).align(8 * HOUR_MS), 8 * HOUR_MS);
+        assert_eq!(FixedUpdate::Minute(15).align(-1), -15 * MINUTE_MS);
+        // A zero period is clamped rather than dividing by zero
+        assert_eq!(FixedUpdate::Minute(0).align(1234), 1234);
+        assert!(!FixedUpdate::Minute(0).is_valid());
+    }
+
+    #[test]
+    fn tick_waits_for_the_next_boundary() {
+        let clock = SimulatedClock::new(10 * HOUR_MS + 30 * MINUTE_MS);
+        let mut timer = Timer::with_clock(FixedUpdate::Hour(1), clock.clone());
+        assert_eq!(timer.get_ts_ms(), 10 * HOUR_MS);
+
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: 11 * HOUR_MS,
+                skipped: 0
+            }
+        );
+        assert_eq!(clock.now_ms(), 11 * HOUR_MS);
+        assert_eq!(timer.get_ts_ms(), 11 * HOUR_MS);
+
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: 12 * HOUR_MS,
+                skipped: 0
+            }
+        );
+        assert_eq!(clock.now_ms(), 12 * HOUR_MS);
+    }
+
+    #[test]
+    fn tick_fires_after_the_offset() {
+        let clock = SimulatedClock::new(10 * HOUR_MS + 30 * MINUTE_MS);
+        let mut timer = Timer::with_clock(FixedUpdate::Hour(1), clock.clone()).with_offset(5_000);
+
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: 11 * HOUR_MS,
+                skipped: 0
+            }
+        );
+        assert_eq!(clock.now_ms(), 11 * HOUR_MS + 5_000);
+
+        // Inside the offset the previous boundary is still pending
+        let clock = SimulatedClock::new(11 * HOUR_MS + 2_000);
+        let mut timer = Timer::with_clock(FixedUpdate::Hour(1), clock.clone()).with_offset(5_000);
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: 11 * HOUR_MS,
+                skipped: 0
+            }
+        );
+        assert_eq!(clock.now_ms(), 11 * HOUR_MS + 5_000);
+    }
+
+    #[test]
+    fn tick_counts_skipped_boundaries() {
+        let clock = SimulatedClock::new(10 * HOUR_MS);
+        let mut timer = Timer::with_clock(FixedUpdate::Hour(1), clock.clone());
+        block_on(timer.tick());
+
+        // Stalled past the 12:00 and 13:00 boundaries
+        clock.set(13 * HOUR_MS + 30 * MINUTE_MS);
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: 13 * HOUR_MS,
+                skipped: 1
+            }
+        );
+
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: 14 * HOUR_MS,
+                skipped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn tick_counts_skipped_cron_boundaries() {
+        let cron = "0 */15 * * * *".parse::<CronSchedule>().unwrap();
+        let clock = SimulatedClock::new(0);
+        let mut timer = Timer::with_clock(cron, clock.clone());
+        assert_eq!(block_on(timer.tick()).ts, 15 * MINUTE_MS);
+
+        clock.set(HOUR_MS + MINUTE_MS);
+        let tick = block_on(timer.tick());
+        assert_eq!(
+            tick,
+            Tick {
+                ts: HOUR_MS,
+                skipped: 2
+            }
+        );
+    }
+
+    #[test]
+    fn update_fires_on_boundaries() {
+        let clock = SimulatedClock::new(HOUR_MS);
+        let mut timer = Timer::with_clock(FixedUpdate::Hour(4), clock.clone());
+        assert_eq!(timer.get_ts_ms(), 0);
+        assert!(!timer.update());
+
+        clock.set(4 * HOUR_MS - 1);
+        assert!(!timer.update());
+        clock.set(4 * HOUR_MS);
+        assert!(timer.update());
+        assert_eq!(timer.get_ts_ms(), 4 * HOUR_MS);
+        assert!(!timer.update());
+
+        // A gap moves straight to the latest boundary
+        clock.set(13 * HOUR_MS);
+        assert!(timer.update());
+        assert_eq!(timer.get_ts_ms(), 12 * HOUR_MS);
+    }
+}
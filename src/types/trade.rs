@@ -1,9 +1,13 @@
+use chrono::Duration;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+use crate::types::kline::Kline;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
 pub enum TradeSide {
     Sell,
     Buy,
+    #[default]
     None,
 }
 
@@ -16,20 +20,343 @@ impl TradeSide {
         }
     }
 }
-impl Default for TradeSide {
-    fn default() -> Self {
-        TradeSide::None
-    }
+
+/// Which of take profit and stop loss is assumed to trade first when a
+/// kline's range contains both, klines don't tell the path inside the bar.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize, Default)]
+pub enum IntrabarOrder {
+    #[default]
+    StopFirst, // pessimistic
+    TargetFirst,
+    // open -> low -> high -> close on up klines, open -> high -> low -> close on down klines
+    CandleDirection,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ExitReason {
+    TakeProfit,
+    StopLoss,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
 pub struct Trade {
+    #[serde(default)]
+    pub id: String, // unique in the journal, empty in backtests
     pub symbol: String,
     pub entry_price: f64,
     pub entry_side: TradeSide,
     pub entry_ts: i64,
     pub exit_price: f64,
+    #[serde(default)]
+    pub exit_ts: i64,
     pub position: f64,
     pub tp_price: f64, // take profit
     pub sl_price: f64, // stop loss
+    #[serde(default)]
+    pub fee: f64,
+    #[serde(default)]
+    pub funding: f64, // net funding received, negative when paid
+}
+
+impl Trade {
+    pub fn open(
+        symbol: String,
+        side: TradeSide,
+        position: f64,
+        price: f64,
+        ts: i64,
+        fee: f64,
+    ) -> Trade {
+        Trade {
+            symbol,
+            entry_price: price,
+            entry_side: side,
+            entry_ts: ts,
+            position,
+            fee,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.exit_ts == 0
+    }
+
+    pub fn close(&mut self, price: f64, ts: i64, fee: f64) {
+        self.exit_price = price;
+        self.exit_ts = ts;
+        self.fee += fee;
+    }
+
+    /// Close `position` of the trade and return the closed part. Fees and
+    /// funding so far are split pro rata, the exit fee goes to the closed
+    /// part. Closing the whole position closes this trade instead.
+    pub fn close_partial(&mut self, position: f64, price: f64, ts: i64, fee: f64) -> Trade {
+        if position >= self.position {
+            self.close(price, ts, fee);
+            return self.clone();
+        }
+        let ratio = position / self.position;
+        let mut closed = self.clone();
+        closed.position = position;
+        closed.fee = self.fee * ratio;
+        closed.funding = self.funding * ratio;
+        closed.close(price, ts, fee);
+        self.position -= position;
+        self.fee -= self.fee * ratio;
+        self.funding -= self.funding * ratio;
+        closed
+    }
+
+    /// Price move times position, before fees and funding.
+    pub fn gross_pnl(&self, price: f64) -> f64 {
+        (price - self.entry_price) * self.position * self.entry_side.value()
+    }
+
+    /// PnL at `price` net of the fees and funding so far.
+    pub fn net_pnl(&self, price: f64) -> f64 {
+        self.gross_pnl(price) + self.funding - self.fee
+    }
+
+    /// Net PnL at the exit, 0 while open.
+    pub fn realized_pnl(&self) -> f64 {
+        if self.is_open() {
+            0.
+        } else {
+            self.net_pnl(self.exit_price)
+        }
+    }
+
+    /// Net PnL at the mark price, 0 once closed.
+    pub fn unrealized_pnl(&self, mark_price: f64) -> f64 {
+        if self.is_open() {
+            self.net_pnl(mark_price)
+        } else {
+            0.
+        }
+    }
+
+    /// Net PnL in units of the initial risk, the loss at `sl_price`. Closed
+    /// trades are measured at the exit, open ones at `mark_price`. None
+    /// without a stop loss.
+    pub fn r_multiple(&self, mark_price: f64) -> Option<f64> {
+        let risk = (self.entry_price - self.sl_price).abs() * self.position;
+        if self.sl_price <= 0. || risk <= 0. {
+            return None;
+        }
+        let price = if self.is_open() {
+            mark_price
+        } else {
+            self.exit_price
+        };
+        Some(self.net_pnl(price) / risk)
+    }
+
+    /// Time since the entry, up to the exit once closed.
+    pub fn holding_duration(&self, now_ts: i64) -> Duration {
+        let end_ts = if self.is_open() { now_ts } else { self.exit_ts };
+        Duration::milliseconds(end_ts - self.entry_ts)
+    }
+
+    /// Book a funding payment, longs pay shorts when the rate is positive.
+    pub fn apply_funding(&mut self, funding_rate: f64, mark_price: f64) -> f64 {
+        let payment = -funding_rate * mark_price * self.position * self.entry_side.value();
+        self.funding += payment;
+        payment
+    }
+
+    /// Take profit or stop loss touched by the kline and the price it exits
+    /// at, the open if the kline gapped through the level.
+    pub fn exit_hit(
+        &self,
+        kline: &Kline,
+        intrabar_order: IntrabarOrder,
+    ) -> Option<(ExitReason, f64)> {
+        let is_long = self.entry_side == TradeSide::Buy;
+        let sl_hit = self.sl_price > 0.
+            && ((is_long && kline.low <= self.sl_price)
+                || (!is_long && kline.high >= self.sl_price));
+        let tp_hit = self.tp_price > 0.
+            && ((is_long && kline.high >= self.tp_price)
+                || (!is_long && kline.low <= self.tp_price));
+        let sl_exit = || {
+            let price = if is_long {
+                self.sl_price.min(kline.open)
+            } else {
+                self.sl_price.max(kline.open)
+            };
+            (ExitReason::StopLoss, price)
+        };
+        let tp_exit = || {
+            let price = if is_long {
+                self.tp_price.max(kline.open)
+            } else {
+                self.tp_price.min(kline.open)
+            };
+            (ExitReason::TakeProfit, price)
+        };
+        match (sl_hit, tp_hit) {
+            (false, false) => None,
+            (true, false) => Some(sl_exit()),
+            (false, true) => Some(tp_exit()),
+            (true, true) => {
+                // A gap through either level at the open decides it
+                let stop_first = if sl_exit().1 == kline.open {
+                    true
+                } else if tp_exit().1 == kline.open {
+                    false
+                } else {
+                    match intrabar_order {
+                        IntrabarOrder::StopFirst => true,
+                        IntrabarOrder::TargetFirst => false,
+                        // Up klines visit the low first, which is a long's stop
+                        IntrabarOrder::CandleDirection => (kline.close >= kline.open) == is_long,
+                    }
+                };
+                Some(if stop_first { sl_exit() } else { tp_exit() })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn trade(side: TradeSide, sl_price: f64, tp_price: f64) -> Trade {
+        let mut trade = Trade::open("BTCUSDT".to_owned(), side, 2., 100., 1, 0.2);
+        trade.sl_price = sl_price;
+        trade.tp_price = tp_price;
+        trade
+    }
+
+    fn kline(open: f64, high: f64, low: f64, close: f64) -> Kline {
+        Kline {
+            open,
+            high,
+            low,
+            close,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn close_partial_splits_long_fees_and_funding() {
+        let mut long = trade(TradeSide::Buy, 0., 0.);
+        long.funding = -0.4;
+        let closed = long.close_partial(0.5, 110., 5, 0.05);
+        assert_eq!((closed.position, closed.exit_ts), (0.5, 5));
+        assert_close(closed.fee, 0.05 + 0.05);
+        assert_close(closed.funding, -0.1);
+        assert_close(closed.realized_pnl(), 5. - 0.1 - 0.1);
+        assert!(long.is_open());
+        assert_close(long.position, 1.5);
+        assert_close(long.fee, 0.15);
+        assert_close(long.funding, -0.3);
+
+        let rest = long.close_partial(1.5, 90., 6, 0.1);
+        assert!(!long.is_open());
+        assert_eq!(rest.exit_ts, 6);
+        assert_close(rest.realized_pnl(), -15. - 0.3 - 0.25);
+    }
+
+    #[test]
+    fn close_partial_splits_short_fees_and_funding() {
+        let mut short = trade(TradeSide::Sell, 0., 0.);
+        short.funding = 0.2;
+        let closed = short.close_partial(1., 90., 5, 0.1);
+        assert_close(closed.fee, 0.2);
+        assert_close(closed.funding, 0.1);
+        assert_close(closed.realized_pnl(), 10. + 0.1 - 0.2);
+        assert_close(short.position, 1.);
+        assert_close(short.unrealized_pnl(95.), 5. + 0.1 - 0.1);
+        // More than the position closes the trade itself
+        let rest = short.close_partial(3., 95., 6, 0.);
+        assert!(!short.is_open());
+        assert_close(rest.position, 1.);
+    }
+
+    #[test]
+    fn r_multiple_is_measured_against_the_stop() {
+        let mut long = trade(TradeSide::Buy, 95., 0.);
+        long.fee = 0.;
+        assert_close(long.r_multiple(97.5).unwrap(), -0.5);
+        long.close(110., 5, 0.);
+        assert_close(long.r_multiple(0.).unwrap(), 2.);
+
+        let mut short = trade(TradeSide::Sell, 105., 0.);
+        short.fee = 1.;
+        assert_close(short.r_multiple(90.).unwrap(), 1.9);
+
+        assert_eq!(trade(TradeSide::Buy, 0., 110.).r_multiple(110.), None);
+        assert_eq!(trade(TradeSide::Buy, 100., 0.).r_multiple(110.), None);
+    }
+
+    #[test]
+    fn exit_hit_orders_stop_and_target_in_the_same_kline() {
+        let long = trade(TradeSide::Buy, 95., 110.);
+        let up = kline(100., 111., 94., 105.);
+        let down = kline(100., 111., 94., 98.);
+        let stop = Some((ExitReason::StopLoss, 95.));
+        let target = Some((ExitReason::TakeProfit, 110.));
+        assert_eq!(long.exit_hit(&up, IntrabarOrder::StopFirst), stop);
+        assert_eq!(long.exit_hit(&up, IntrabarOrder::TargetFirst), target);
+        assert_eq!(long.exit_hit(&up, IntrabarOrder::CandleDirection), stop);
+        assert_eq!(long.exit_hit(&down, IntrabarOrder::CandleDirection), target);
+
+        let short = trade(TradeSide::Sell, 105., 90.);
+        let both = kline(100., 106., 89., 95.);
+        let stop = Some((ExitReason::StopLoss, 105.));
+        let target = Some((ExitReason::TakeProfit, 90.));
+        assert_eq!(short.exit_hit(&both, IntrabarOrder::StopFirst), stop);
+        assert_eq!(short.exit_hit(&both, IntrabarOrder::TargetFirst), target);
+        // Down klines visit the high first, which is a short's stop
+        assert_eq!(short.exit_hit(&both, IntrabarOrder::CandleDirection), stop);
+    }
+
+    #[test]
+    fn exit_hit_gaps_exit_at_the_open() {
+        let long = trade(TradeSide::Buy, 95., 110.);
+        let gap_down = kline(93., 111., 92., 100.);
+        assert_eq!(
+            long.exit_hit(&gap_down, IntrabarOrder::TargetFirst),
+            Some((ExitReason::StopLoss, 93.))
+        );
+        let gap_up = kline(112., 113., 94., 100.);
+        assert_eq!(
+            long.exit_hit(&gap_up, IntrabarOrder::StopFirst),
+            Some((ExitReason::TakeProfit, 112.))
+        );
+        assert_eq!(
+            long.exit_hit(&kline(100., 105., 96., 101.), IntrabarOrder::StopFirst),
+            None
+        );
+    }
+
+    #[test]
+    fn open_trades_deserialize_without_exit_and_fee() {
+        let json = r#"{
+            "symbol": "BTCUSDT",
+            "entry_price": 100.0,
+            "entry_side": "Buy",
+            "entry_ts": 1,
+            "exit_price": 0.0,
+            "position": 2.0,
+            "tp_price": 0.0,
+            "sl_price": 0.0
+        }"#;
+        let trade: Trade = serde_json::from_str(json).unwrap();
+        assert!(trade.is_open());
+        assert_eq!((trade.fee, trade.funding), (0., 0.));
+        assert!(trade.id.is_empty());
+    }
 }